@@ -0,0 +1,54 @@
+// How much time skipping known interiors and stopping on loops saves, on
+// views that are mostly inside the set. Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use num::Complex;
+
+use mandlebrot::escape::*;
+use mandlebrot::fractal::*;
+
+/// Every pixel of a 160x120 view, 1.6 across, around `centre`.
+fn points(centre: Complex<f64>) -> Vec<Complex<f64>> {
+    let bounds = (160, 120);
+    let spacing = 1.6 / bounds.0 as f64;
+    let mut points = Vec::new();
+    for row in 0..bounds.1 {
+        for column in 0..bounds.0 {
+            let offset = Complex { re: (column as f64 - bounds.0 as f64 / 2.0) * spacing,
+                                   im: (bounds.1 as f64 / 2.0 - row as f64) * spacing };
+            points.push(centre + offset);
+        }
+    }
+    points
+}
+
+fn bench_shortcuts(c: &mut Criterion, name: &str, fractal: &dyn Fractal, centre: Complex<f64>) {
+    let points = points(centre);
+    let mut group = c.benchmark_group(name);
+
+    group.bench_function("shortcuts", |b| {
+        b.iter(|| points.iter().map(|&p| escape_time(fractal, p, 255)).collect::<Vec<_>>())
+    });
+    group.bench_function("every iteration", |b| {
+        b.iter(|| {
+            points.iter()
+                .map(|&p| escape_time_every_iteration(fractal, p, 255))
+                .collect::<Vec<_>>()
+        })
+    });
+    group.finish();
+}
+
+fn bench_mandelbrot(c: &mut Criterion) {
+    // Mostly the main cardioid and the bulb next to it
+    bench_shortcuts(c, "mandelbrot", &Mandelbrot, Complex { re: -0.5, im: 0.0 });
+}
+
+fn bench_rabbit(c: &mut Criterion) {
+    // The Douady rabbit, whose inside is all drawn into a loop of three
+    let rabbit = Julia { c: Complex { re: -0.123, im: 0.745 } };
+    bench_shortcuts(c, "rabbit", &rabbit, Complex { re: 0.0, im: 0.0 });
+}
+
+criterion_group!(benches, bench_mandelbrot, bench_rabbit);
+criterion_main!(benches);
@@ -0,0 +1,135 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use super::deep::Decimal;
+use super::view::View;
+
+/// How long each frame of an animated GIF shows for, in hundredths of a
+/// second.
+const GIF_FRAME_DELAY: u16 = 4;
+
+/// The views for each of `frames` frames of a zoom from `start` into
+/// `target`, ending up `zoom` times narrower.
+///
+/// The view narrows exponentially, by the same factor every frame, so the
+/// zoom looks steady. The centre moves in step with it, quickly while the
+/// view is wide and slowly once it's narrow, so that `target` glides
+/// steadily into the middle of the picture rather than racing off the edge.
+pub fn zoom_views(start: &View, target: &(Decimal, Decimal), zoom: f64, frames: usize)
+    -> Vec<View>
+{
+    let offset = (start.centre.0.minus(&target.0), start.centre.1.minus(&target.1));
+
+    (0..frames).map(|frame| {
+        let t = if frames > 1 { frame as f64 / (frames - 1) as f64 } else { 0.0 };
+        let scale = zoom.powf(-t);
+        let drift = if zoom == 1.0 {
+            1.0 - t
+        } else {
+            (scale - 1.0 / zoom) / (1.0 - 1.0 / zoom)
+        };
+
+        View {
+            centre: (target.0.plus(&offset.0.times(drift)),
+                     target.1.plus(&offset.1.times(drift))),
+            spacing: (start.spacing.0 * scale, start.spacing.1 * scale),
+            rotation: start.rotation,
+        }
+    }).collect()
+}
+
+/// The file for frame number `frame` of `frames` in a PNG sequence: `path`
+/// with the frame number, padded to the same width for every frame, added
+/// before the extension.
+pub fn frame_path(path: &str, frame: usize, frames: usize) -> String {
+    let digits = (frames.max(2) - 1).to_string().len();
+    let name_start = path.rfind('/').map_or(0, |i| i + 1);
+    match path[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, extension) = path.split_at(name_start + dot);
+            format!("{}-{:0width$}{}", stem, frame, extension, width = digits)
+        }
+        _ => format!("{}-{:0width$}", path, frame, width = digits)
+    }
+}
+
+/// An animated GIF being written a frame at a time.
+pub struct Gif {
+    encoder: gif::Encoder<File>,
+    bounds: (u16, u16),
+}
+
+impl Gif {
+    /// Start a GIF at `path` for frames with dimensions `bounds`, which loops
+    /// forever.
+    pub fn create(path: &str, bounds: (usize, usize)) -> Result<Gif, String> {
+        let too_big = || format!("a GIF can't be more than {} pixels across", u16::MAX);
+        let bounds = (u16::try_from(bounds.0).map_err(|_| too_big())?,
+                      u16::try_from(bounds.1).map_err(|_| too_big())?);
+
+        let file = File::create(path)
+            .map_err(|e| format!("failed to create '{}': {}", path, e))?;
+        let failed = |e: gif::EncodingError| format!("failed to write '{}': {}", path, e);
+        let mut encoder = gif::Encoder::new(file, bounds.0, bounds.1, &[]).map_err(failed)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(failed)?;
+
+        Ok(Gif { encoder, bounds })
+    }
+
+    /// Add the next frame, one RGB pixel per three bytes, reduced to the
+    /// 256 colours that suit it best.
+    pub fn add_frame(&mut self, pixels: &[u8]) -> Result<(), std::io::Error> {
+        let mut frame = gif::Frame::from_rgb(self.bounds.0, self.bounds.1, pixels);
+        frame.delay = GIF_FRAME_DELAY;
+        self.encoder.write_frame(&frame).map_err(std::io::Error::other)
+    }
+}
+
+#[test]
+fn test_zoom_views() {
+    let pair = |re: &str, im: &str| (re.parse::<Decimal>().unwrap(), im.parse::<Decimal>().unwrap());
+    let bounds = (400, 300);
+    let start = View::from_corners(bounds, &pair("-2", "1.5"), &pair("2", "-1.5"));
+    let target = pair("-0.75", "0.1");
+    let views = zoom_views(&start, &target, 1000.0, 4);
+    assert_eq!(views.len(), 4);
+
+    // The upper left and lower right corners
+    let f64s = |view: &View| {
+        let (upper_left, lower_right) = (view.point(bounds, (0.0, 0.0)),
+                                         view.point(bounds, (400.0, 300.0)));
+        [upper_left.re, upper_left.im, lower_right.re, lower_right.im]
+    };
+    let close = |a: [f64; 4], b: [f64; 4]| a.iter().zip(&b).all(|(x, y)| (x - y).abs() < 1e-12);
+
+    // Starts where it was asked to and ends 1000 times narrower on the target
+    assert!(close(f64s(&views[0]), [-2.0, 1.5, 2.0, -1.5]));
+    assert!(close(f64s(&views[3]), [-0.752, 0.1015, -0.748, 0.0985]));
+
+    // Ten times narrower each frame, with the target moving towards the
+    // middle of the picture, and never out of it
+    let across = |view: &View| {
+        let [left, top, right, bottom] = f64s(view);
+        (right - left, top - bottom, (-0.75 - left) / (right - left))
+    };
+    for pair in views.windows(2) {
+        let (wide, narrow) = (across(&pair[0]), across(&pair[1]));
+        assert!(close([wide.0 / narrow.0, wide.1 / narrow.1, 0.0, 0.0], [10.0, 10.0, 0.0, 0.0]));
+        assert!(wide.2 > 0.0 && wide.2 < narrow.2 && narrow.2 <= 0.5);
+    }
+
+    // Panning without zooming moves evenly, and keeps the view's rotation
+    let turned = View { rotation: 0.5, ..start };
+    let views = zoom_views(&turned, &target, 1.0, 3);
+    assert_eq!(views[1].rotation, 0.5);
+    assert!(close(f64s(&View { rotation: 0.0, ..views[1].clone() }),
+                  [-2.375, 1.55, 1.625, -1.45]));
+}
+
+#[test]
+fn test_frame_path() {
+    assert_eq!(frame_path("zoom.png", 7, 120), "zoom-007.png");
+    assert_eq!(frame_path("out/zoom.png", 12, 20), "out/zoom-12.png");
+    assert_eq!(frame_path("zoom", 3, 10), "zoom-3");
+    assert_eq!(frame_path("out.d/zoom", 0, 1), "out.d/zoom-0");
+    assert_eq!(frame_path(".png", 1, 2), ".png-1");
+}
@@ -0,0 +1,340 @@
+use num::{BigInt, Complex, ToPrimitive, Zero};
+use std::convert::TryFrom;
+use std::str::FromStr;
+use super::escape::{smooth_count, ESCAPE_RADIUS};
+
+/// The furthest a `Decimal` can be from 1 in either direction, as a power of
+/// ten. Far deeper than any zoom can be rendered, but it stops a typo like
+/// `1e-1000000000` from taking forever to scale.
+const MAX_EXPONENT: i64 = 10_000;
+
+/// A number written in decimal, kept exactly: `digits` times ten to the
+/// power `exponent`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decimal {
+    digits: BigInt,
+    exponent: i64,
+}
+
+impl FromStr for Decimal {
+    type Err = String;
+
+    /// Parse numbers like `-0.75`, `1.5e-60` or `12`.
+    fn from_str(s: &str) -> Result<Decimal, String> {
+        let invalid = || format!("'{}' isn't a decimal number", s);
+
+        let (mantissa, exponent) = match s.find(['e', 'E']) {
+            Some(index) => (&s[..index],
+                            s[index + 1..].parse::<i64>().map_err(|_| invalid())?),
+            None => (s, 0)
+        };
+        let (sign, unsigned) = match mantissa.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", mantissa.strip_prefix('+').unwrap_or(mantissa))
+        };
+        let (whole, fraction) = match unsigned.find('.') {
+            Some(index) => (&unsigned[..index], &unsigned[index + 1..]),
+            None => (unsigned, "")
+        };
+
+        let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if whole.len() + fraction.len() == 0 || !all_digits(whole) || !all_digits(fraction) {
+            return Err(invalid());
+        }
+
+        let exponent = exponent.checked_sub(fraction.len() as i64)
+            .filter(|e| (-MAX_EXPONENT..=MAX_EXPONENT).contains(e))
+            .ok_or_else(|| format!("'{}' is out of range: the most digits after the point, \
+                                    or power of ten, is {}", s, MAX_EXPONENT))?;
+        let digits = BigInt::from_str(&format!("{}{}{}", sign, whole, fraction))
+            .map_err(|_| invalid())?;
+        Ok(Decimal { digits, exponent })
+    }
+}
+
+/// Ten to the power `n`, which can't be negative.
+fn power_of_ten(n: i64) -> BigInt {
+    let n = usize::try_from(n).expect("negative power of ten");
+    num::pow(BigInt::from(10), n)
+}
+
+impl Decimal {
+    /// `self - other`, exactly.
+    pub fn minus(&self, other: &Decimal) -> Decimal {
+        let exponent = self.exponent.min(other.exponent);
+        let scaled = |d: &Decimal| &d.digits * power_of_ten(d.exponent - exponent);
+        Decimal { digits: scaled(self) - scaled(other), exponent }
+    }
+
+    /// `self + other`, exactly.
+    pub fn plus(&self, other: &Decimal) -> Decimal {
+        let negated = Decimal { digits: -&other.digits, exponent: other.exponent };
+        self.minus(&negated)
+    }
+
+    /// The shortest decimal that reads back as `x`. Infinities, which a view
+    /// too wide for `f64` can produce, are clamped to the largest finite
+    /// `f64` of the same sign, and NaN becomes zero.
+    pub fn from_f64(x: f64) -> Decimal {
+        let x = if x.is_nan() { 0.0 } else { x.clamp(f64::MIN, f64::MAX) };
+        format!("{:e}", x).parse().unwrap()
+    }
+
+    /// `self` times `factor`, or rather `Decimal::from_f64(factor)`.
+    pub fn times(&self, factor: f64) -> Decimal {
+        let factor = Decimal::from_f64(factor);
+        Decimal { digits: &self.digits * factor.digits,
+                  exponent: self.exponent + factor.exponent }
+    }
+
+    /// The nearest `f64`.
+    pub fn to_f64(&self) -> f64 {
+        // The standard library rounds correctly however many digits there are
+        format!("{}e{}", self.digits, self.exponent).parse().unwrap()
+    }
+
+    /// In fixed point, with `bits` bits after the binary point, rounded
+    /// towards zero.
+    pub fn to_fixed(&self, bits: u32) -> BigInt {
+        if self.exponent >= 0 {
+            (&self.digits * power_of_ten(self.exponent)) << bits
+        } else {
+            (&self.digits << bits) / power_of_ten(-self.exponent)
+        }
+    }
+}
+
+/// A fixed point number, with `bits` bits after the binary point, as the
+/// nearest `f64`.
+fn fixed_to_f64(x: &BigInt, bits: u32) -> f64 {
+    // More than enough to fill an f64's mantissa
+    let excess = x.bits().saturating_sub(64);
+    let top = (x >> excess).to_f64().unwrap();
+    top * 2f64.powi(excess as i32 - bits as i32)
+}
+
+/// The orbit of zero under z -> z^2 + c, with `c` in fixed point, up to and
+/// including the first point outside `ESCAPE_RADIUS` or else `limit`
+/// iterations.
+fn reference_orbit(c: &Complex<BigInt>, bits: u32, limit: usize) -> Vec<Complex<f64>> {
+    let escape = BigInt::from((ESCAPE_RADIUS * ESCAPE_RADIUS) as i64) << bits;
+    let mut orbit = Vec::with_capacity(limit + 1);
+    let mut z = Complex { re: BigInt::zero(), im: BigInt::zero() };
+
+    for _ in 0..limit {
+        orbit.push(Complex { re: fixed_to_f64(&z.re, bits),
+                             im: fixed_to_f64(&z.im, bits) });
+
+        let re2 = (&z.re * &z.re) >> bits;
+        let im2 = (&z.im * &z.im) >> bits;
+        if &re2 + &im2 > escape {
+            return orbit;
+        }
+        let re_im = (&z.re * &z.im) >> bits;
+        z = Complex { re: re2 - im2 + &c.re, im: (re_im << 1) + &c.im };
+    }
+
+    orbit.push(Complex { re: fixed_to_f64(&z.re, bits),
+                         im: fixed_to_f64(&z.im, bits) });
+    orbit
+}
+
+/// Everything needed to find the escape time of any point in a deep zoom
+/// of the Mandelbrot set, past where `f64` can tell neighbouring pixels
+/// apart.
+///
+/// One point in the middle of the view, the reference, is iterated in
+/// fixed point with as many bits as the zoom needs. Every pixel is then
+/// iterated as a small difference from the reference orbit, which `f64`
+/// holds perfectly well: if the reference is at `Z` and the pixel at
+/// `Z + d`, with `dc` between their values of `c`, the difference goes
+/// d -> 2Zd + d^2 + dc. This works down to pixels about 1e-300 across,
+/// where `f64` runs out of exponent.
+pub struct Perturbation {
+    /// The orbit of the reference point, from zero.
+    orbit: Vec<Complex<f64>>,
+}
+
+impl Perturbation {
+    /// Compute the reference orbit of `centre`, precisely enough to tell
+    /// apart points `spacing` away from each other, iterating at most
+    /// `limit` times.
+    pub fn new(centre: &(Decimal, Decimal), spacing: f64, limit: usize) -> Perturbation {
+        // Enough bits to resolve a pixel, and then some for the error that
+        // builds up over the orbit
+        let bits = 64 + (-spacing.abs().log2()).clamp(0.0, 4096.0) as u32;
+        let c = Complex { re: centre.0.to_fixed(bits), im: centre.1.to_fixed(bits) };
+        Perturbation { orbit: reference_orbit(&c, bits, limit) }
+    }
+
+    /// The escape time of the point `dc` away from the reference, as for
+    /// `escape_time`.
+    pub fn escape_time(&self, dc: Complex<f64>, limit: usize) -> Option<f64> {
+        let mut d = Complex { re: 0.0, im: 0.0 };
+        let mut m = 0;
+        for i in 0..limit {
+            let z = self.orbit[m] + d;
+            if z.norm_sqr() > ESCAPE_RADIUS * ESCAPE_RADIUS {
+                return Some(smooth_count(i, z, 2.0));
+            }
+
+            // Once the pixel's orbit comes closer to zero than to the
+            // reference's, the difference has grown too big to trust: the
+            // pixels around here would all come out the same colour. Rebase
+            // onto the start of the reference orbit, where z is zero, so that
+            // the difference is the pixel's z itself, and small. The same goes
+            // if the reference escaped before this pixel did.
+            if z.norm_sqr() < d.norm_sqr() || m + 1 == self.orbit.len() {
+                d = z;
+                m = 0;
+            }
+
+            d = 2.0 * self.orbit[m] * d + d * d + dc;
+            m += 1;
+        }
+
+        None
+    }
+}
+
+#[test]
+fn test_parse_decimal() {
+    let decimal = |digits: i64, exponent| Decimal { digits: BigInt::from(digits), exponent };
+    assert_eq!("1.25".parse(), Ok(decimal(125, -2)));
+    assert_eq!("-0.0625e-3".parse(), Ok(decimal(-625, -7)));
+    assert_eq!("+12".parse(), Ok(decimal(12, 0)));
+    assert_eq!("1e-50".parse(), Ok(decimal(1, -50)));
+    assert_eq!(".5E2".parse(), Ok(decimal(5, 1)));
+
+    assert_eq!("1e-10000".parse(), Ok(decimal(1, -10000)));
+    assert_eq!("1e10000".parse(), Ok(decimal(1, 10000)));
+
+    for bad in ["", "-", ".", "1.2.3", "e5", "1e", "1e2.5", "--1", "0x10", "1,5",
+                "1e-1000000000", "1e10001", "0.1e-10000", "1e-9223372036854775808"].iter() {
+        assert!(bad.parse::<Decimal>().is_err(), "parsed '{}'", bad);
+    }
+}
+
+#[test]
+fn test_decimal_arithmetic() {
+    let decimal = |s: &str| s.parse::<Decimal>().unwrap();
+    assert_eq!(decimal("1.25").minus(&decimal("-0.0625")).to_f64(), 1.3125);
+    assert_eq!(decimal("1e-60").to_f64(), 1e-60);
+
+    // Too close together for f64, but not for Decimal
+    let (a, b) = (decimal("0.30000000000000000000000000000000000001"), decimal("0.3"));
+    assert_eq!(a.to_f64(), b.to_f64());
+    assert_eq!(a.minus(&b).to_f64(), 1e-38);
+    assert_eq!(a.plus(&b).minus(&b), a);
+    assert_eq!(a.times(0.5).to_f64(), 0.15);
+    assert_eq!(a.times(1e-40).minus(&decimal("3e-41")).to_f64(), 1e-78);
+    assert_eq!(Decimal::from_f64(f64::INFINITY).to_f64(), f64::MAX);
+    assert_eq!(Decimal::from_f64(f64::NEG_INFINITY).to_f64(), f64::MIN);
+    assert_eq!(Decimal::from_f64(f64::NAN).to_f64(), 0.0);
+    assert_eq!(decimal("1e10000").times(f64::INFINITY).to_f64(), f64::INFINITY);
+
+    assert_eq!(decimal("-1.25").to_fixed(8), BigInt::from(-320));
+    assert_eq!(decimal("3").to_fixed(4), BigInt::from(48));
+    assert_eq!(fixed_to_f64(&decimal("-0.0625").to_fixed(200), 200), -0.0625);
+    assert_eq!(fixed_to_f64(&decimal("1e-60").to_fixed(400), 400), 1e-60);
+}
+
+#[test]
+fn test_reference_orbit() {
+    let fixed = |re: &str, im: &str| Complex { re: re.parse::<Decimal>().unwrap().to_fixed(100),
+                                              im: im.parse::<Decimal>().unwrap().to_fixed(100) };
+    let c = |re, im| Complex { re, im };
+
+    // i is preperiodic: 0, i, -1 + i, -i, -1 + i, ...
+    let orbit = reference_orbit(&fixed("0", "1"), 100, 5);
+    assert_eq!(orbit, vec![c(0.0, 0.0), c(0.0, 1.0), c(-1.0, 1.0),
+                           c(0.0, -1.0), c(-1.0, 1.0), c(0.0, -1.0)]);
+
+    // 10 escapes at the third step, and that's where the orbit stops
+    let orbit = reference_orbit(&fixed("10", "0"), 100, 50);
+    assert_eq!(orbit, vec![c(0.0, 0.0), c(10.0, 0.0), c(110.0, 0.0), c(12110.0, 0.0)]);
+}
+
+#[cfg(test)]
+fn corner(s: &str) -> (Decimal, Decimal) {
+    super::parse::parse_pair(s, ',').unwrap()
+}
+
+/// The escape time of a single point, iterated entirely in fixed point.
+#[cfg(test)]
+fn exact_escape_time(c: &Complex<BigInt>, bits: u32, limit: usize) -> Option<f64> {
+    let orbit = reference_orbit(c, bits, limit);
+    let last = *orbit.last().unwrap();
+    if last.norm_sqr() > ESCAPE_RADIUS * ESCAPE_RADIUS {
+        Some(smooth_count(orbit.len() - 1, last, 2.0))
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_perturbation_matches_f64() {
+    use super::escape::escape_time;
+    use super::fractal::Mandelbrot;
+    use super::view::View;
+
+    // Shallow enough for plain f64 to get right. No row is on the real axis,
+    // where the antenna is so thin that the least rounding knocks points off.
+    let bounds = (60, 40);
+    let view = View::from_corners(bounds, &corner("-2.1,1.17"), &corner("0.9,-0.83"));
+    let perturbation = Perturbation::new(&view.centre, view.spacing.0, 200);
+
+    let mut differ = 0;
+    for row in 0..bounds.1 {
+        for column in 0..bounds.0 {
+            let pixel = (column as f64, row as f64);
+            match (escape_time(&Mandelbrot, view.point(bounds, pixel), 200),
+                   perturbation.escape_time(view.offset(bounds, pixel), 200)) {
+                (Some(a), Some(b)) if (a - b).abs() < 1e-6 => {}
+                (None, None) => {}
+                _ => differ += 1
+            }
+        }
+    }
+    // A pixel right on the edge of the set may go either way
+    assert!(differ <= 2, "{} pixels differ", differ);
+}
+
+#[test]
+fn test_deep_zoom() {
+    use super::view::View;
+
+    // 1e-50 across, around i, where the set is a thin tree all the way down
+    let bounds = (24, 16);
+    let view = View::from_corners(
+        bounds,
+        &corner("-1.2e-50,1.00000000000000000000000000000000000000000000000000800"),
+        &corner("1.2e-50,0.99999999999999999999999999999999999999999999999999200"));
+    let perturbation = Perturbation::new(&view.centre, view.spacing.0, 255);
+
+    let bits = 300;
+    let mut counts = Vec::new();
+    for row in 0..bounds.1 {
+        for column in 0..bounds.0 {
+            let offset = view.offset(bounds, (column as f64, row as f64));
+            let c = Complex {
+                re: view.centre.0.plus(&Decimal::from_f64(offset.re)).to_fixed(bits),
+                im: view.centre.1.plus(&Decimal::from_f64(offset.im)).to_fixed(bits),
+            };
+            let exact = exact_escape_time(&c, bits, 255);
+            let perturbed = perturbation.escape_time(offset, 255);
+            match (exact, perturbed) {
+                (Some(a), Some(b)) => {
+                    assert!((a - b).abs() < 1e-3, "{} vs {} at {:?}", a, b, (column, row));
+                    counts.push(a);
+                }
+                (a, b) => assert_eq!(a, b, "at {:?}", (column, row))
+            }
+        }
+    }
+
+    // Not just one flat colour
+    let least = counts.iter().cloned().fold(f64::INFINITY, f64::min);
+    let most = counts.iter().cloned().fold(0.0, f64::max);
+    assert!(most - least > 1.0, "counts only range from {} to {}", least, most);
+}
@@ -0,0 +1,131 @@
+use num::Complex;
+use super::fractal::*;
+#[cfg(test)]
+use super::view::View;
+
+/// Points are counted as escaped once they're this far from the origin.
+/// Much further out than the usual 2, so that the smooth count is accurate.
+pub const ESCAPE_RADIUS: f64 = 256.0;
+
+/// Try to determine if `point` is in the set drawn by `fractal`, using at
+/// most `limit` iterations to decide.
+///
+/// If `point` is not a member, return `Some(mu)`, where `mu` is the
+/// normalised iteration count: the number of iterations it took for `z` to
+/// leave a large circle centered on the origin, less a fraction for how far
+/// past it went. It varies smoothly from one point to the next, which avoids
+/// bands of colour. If `point` seems to be a member (more precisely, if we
+/// reached the iteration limit without being able to prove that it is not a
+/// member), return `None`.
+///
+/// Points the fractal knows are inside are skipped straight away, and
+/// orbits that come back exactly to where they've been before will go
+/// round the same loop forever, and never escape. These are most of the
+/// points inside the set, and without this they'd take all `limit`
+/// iterations.
+pub fn escape_time(fractal: &dyn Fractal, point: Complex<f64>, limit: usize) -> Option<f64> {
+    if fractal.known_interior(point) {
+        return None;
+    }
+
+    // Brent's method: compare against a point saved at each power of two
+    // iterations, which finds a loop of any length within a few times its
+    // length of the orbit settling into it
+    let (mut z, c) = fractal.start(point);
+    let mut saved = z;
+    let mut next_save = 1;
+    for i in 0..limit {
+        if z.norm_sqr() > ESCAPE_RADIUS * ESCAPE_RADIUS {
+            return Some(smooth_count(i, z, fractal.degree()));
+        }
+        z = fractal.step(z, c);
+
+        if z == saved {
+            return None;
+        }
+        if i + 1 == next_save {
+            saved = z;
+            next_save *= 2;
+        }
+    }
+
+    None
+}
+
+/// `escape_time` the long way round, for checking the shortcuts against and
+/// seeing how much time they save.
+pub fn escape_time_every_iteration(fractal: &dyn Fractal, point: Complex<f64>, limit: usize)
+    -> Option<f64>
+{
+    let (mut z, c) = fractal.start(point);
+    for i in 0..limit {
+        if z.norm_sqr() > ESCAPE_RADIUS * ESCAPE_RADIUS {
+            return Some(smooth_count(i, z, fractal.degree()));
+        }
+        z = fractal.step(z, c);
+    }
+
+    None
+}
+
+/// The normalised iteration count for a point that escaped to `z` after
+/// `i` iterations of a map of the given `degree`.
+pub fn smooth_count(i: usize, z: Complex<f64>, degree: f64) -> f64 {
+    let log_modulus = z.norm_sqr().ln() / 2.0;
+    (i as f64 + 1.0 - log_modulus.ln() / degree.ln()).max(0.0)
+}
+
+#[test]
+fn test_escape_time() {
+    let at = |re, im| Complex { re, im };
+    assert_eq!(escape_time(&Mandelbrot, at(0.0, 0.0), 100), None);
+    assert_eq!(escape_time(&Mandelbrot, at(-1.0, 0.0), 100), None);
+
+    // Further out escapes sooner, and the count changes smoothly
+    let count = |re| escape_time(&Mandelbrot, at(re, 0.0), 100).unwrap();
+    assert!(count(1.0) > count(2.0));
+    assert!(count(2.0) > count(3.0));
+    assert!((count(0.5) - count(0.5001)).abs() < 0.01);
+
+    // The Julia set for c = 0 is the unit disc
+    let julia = Julia { c: at(0.0, 0.0) };
+    assert_eq!(escape_time(&julia, at(0.6, -0.6), 100), None);
+    assert!(escape_time(&julia, at(0.8, -0.8), 100).is_some());
+
+    // The Tricorn is the Mandelbrot set's mirror image along the real axis
+    // only for real points; off it they differ
+    assert_eq!(escape_time(&Tricorn, at(-1.0, 0.0), 100), None);
+    assert!(escape_time(&Tricorn, at(-0.1, 0.8), 100).is_some());
+    assert_eq!(escape_time(&Mandelbrot, at(-0.1, 0.8), 100), None);
+
+    // Multibrot with d = 2 is the Mandelbrot set, and d = 3 is smaller
+    let square = Multibrot { d: 2.0 };
+    assert_eq!(escape_time(&square, at(-1.0, 0.0), 100), None);
+    assert!(escape_time(&Multibrot { d: 3.0 }, at(-1.0, 0.0), 100).is_some());
+}
+
+#[test]
+fn test_escape_time_shortcuts() {
+    // The shortcuts give exactly the same image; `cargo bench` shows how
+    // much sooner
+    let compare = |fractal: &dyn Fractal, centre: (&str, &str), width| {
+        let bounds = (160, 120);
+        let centre = (centre.0.parse().unwrap(), centre.1.parse().unwrap());
+        let view = View::from_centre(bounds, centre, width, 0.0);
+        for row in 0..bounds.1 {
+            for column in 0..bounds.0 {
+                let point = view.point(bounds, (column as f64, row as f64));
+                assert_eq!(escape_time(fractal, point, 255),
+                           escape_time_every_iteration(fractal, point, 255),
+                           "at {}", point);
+            }
+        }
+    };
+
+    // Mostly the main cardioid and the bulb next to it
+    compare(&Mandelbrot, ("-0.5", "0"), 1.6);
+
+    // The Douady rabbit, whose inside is all drawn into a loop of three
+    let rabbit = Julia { c: Complex { re: -0.123, im: 0.745 } };
+    compare(&rabbit, ("0", "0"), 1.6);
+}
@@ -0,0 +1,192 @@
+use num::Complex;
+
+/// An escape-time fractal: a map applied over and over to `z`, starting from
+/// a point that depends on the pixel, which either escapes to infinity or
+/// doesn't.
+///
+/// Fractals are shared between the threads rendering each band, hence `Sync`.
+pub trait Fractal: Sync {
+    /// The starting `z`, and the `c` to use at every step, for the point
+    /// `point` on the complex plane.
+    fn start(&self, point: Complex<f64>) -> (Complex<f64>, Complex<f64>) {
+        (Complex { re: 0.0, im: 0.0 }, point)
+    }
+
+    /// One application of the map.
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64>;
+
+    /// How fast `z` grows once it's large, as the power it's raised to each
+    /// step. Used to smooth out the iteration count.
+    fn degree(&self) -> f64 {
+        2.0
+    }
+
+    /// Whether `point` can be seen to be in the set without iterating at
+    /// all. It's fine to say no when it is.
+    fn known_interior(&self, _point: Complex<f64>) -> bool {
+        false
+    }
+
+    /// Whether deep zooms can be drawn with `deep::Perturbation`, which only
+    /// knows z -> z^2 + c starting from zero.
+    fn perturbable(&self) -> bool {
+        false
+    }
+}
+
+/// z -> z^2 + c, starting from zero.
+pub struct Mandelbrot;
+
+impl Fractal for Mandelbrot {
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        z * z + c
+    }
+
+    /// Points in the main cardioid or the period-2 bulb to its left, which
+    /// between them are most of the set.
+    fn known_interior(&self, c: Complex<f64>) -> bool {
+        let x = c.re - 0.25;
+        let q = x * x + c.im * c.im;
+        let in_cardioid = q * (q + x) <= c.im * c.im / 4.0;
+        let in_bulb = (c.re + 1.0) * (c.re + 1.0) + c.im * c.im <= 1.0 / 16.0;
+        in_cardioid || in_bulb
+    }
+
+    fn perturbable(&self) -> bool {
+        true
+    }
+}
+
+/// z -> z^2 + c for a fixed `c`, starting from the point itself.
+pub struct Julia {
+    pub c: Complex<f64>,
+}
+
+impl Fractal for Julia {
+    fn start(&self, point: Complex<f64>) -> (Complex<f64>, Complex<f64>) {
+        (point, self.c)
+    }
+
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        z * z + c
+    }
+}
+
+/// z -> (|Re z| + i|Im z|)^2 + c. Upside down compared to the usual
+/// pictures, which put the positive imaginary axis at the bottom.
+pub struct BurningShip;
+
+impl Fractal for BurningShip {
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        let folded = Complex { re: z.re.abs(), im: z.im.abs() };
+        folded * folded + c
+    }
+}
+
+/// z -> conj(z)^2 + c, also known as the Mandelbar set.
+pub struct Tricorn;
+
+impl Fractal for Tricorn {
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        let conjugate = z.conj();
+        conjugate * conjugate + c
+    }
+}
+
+/// z -> z^d + c for any real power `d` greater than one.
+pub struct Multibrot {
+    pub d: f64,
+}
+
+impl Fractal for Multibrot {
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        // powf goes through polar form, which can't cope with zero
+        if z.re == 0.0 && z.im == 0.0 {
+            return c;
+        }
+        z.powf(self.d) + c
+    }
+
+    fn degree(&self) -> f64 {
+        self.d
+    }
+}
+
+/// Parse the name of a fractal: `mandelbrot`, `julia:RE,IM`, `burning-ship`,
+/// `tricorn` or `multibrot:D`.
+pub fn parse_fractal(s: &str) -> Option<Box<dyn Fractal>> {
+    let (name, parameter) = match s.find(':') {
+        Some(index) => (&s[..index], Some(&s[index + 1..])),
+        None => (s, None)
+    };
+
+    match (name, parameter) {
+        ("mandelbrot", None) => Some(Box::new(Mandelbrot)),
+        ("burning-ship", None) => Some(Box::new(BurningShip)),
+        ("tricorn", None) => Some(Box::new(Tricorn)),
+        ("julia", Some(c)) => {
+            let c = super::parse::parse_complex(c)?;
+            Some(Box::new(Julia { c }))
+        }
+        ("multibrot", Some(d)) => {
+            match d.parse() {
+                Ok(d) if d > 1.0 => Some(Box::new(Multibrot { d })),
+                _ => None
+            }
+        }
+        _ => None
+    }
+}
+
+#[cfg(test)]
+fn c(re: f64, im: f64) -> Complex<f64> {
+    Complex { re, im }
+}
+
+#[test]
+fn test_steps() {
+    let zero = c(0.0, 0.0);
+    assert_eq!(Mandelbrot.step(c(1.0, 2.0), c(0.5, 0.0)), c(-2.5, 4.0));
+    assert_eq!(BurningShip.step(c(-1.0, -2.0), zero), c(-3.0, 4.0));
+    assert_eq!(Tricorn.step(c(1.0, 2.0), zero), c(-3.0, -4.0));
+
+    let julia = Julia { c: c(0.25, 0.5) };
+    assert_eq!(julia.start(c(1.0, 1.0)), (c(1.0, 1.0), c(0.25, 0.5)));
+    assert_eq!(Mandelbrot.start(c(1.0, 1.0)), (zero, c(1.0, 1.0)));
+
+    let cube = Multibrot { d: 3.0 }.step(c(1.0, 1.0), zero);
+    assert!((cube - c(-2.0, 2.0)).norm() < 1e-12);
+    assert_eq!(Multibrot { d: 2.5 }.step(zero, c(0.5, 0.5)), c(0.5, 0.5));
+    assert_eq!(Multibrot { d: 2.5 }.degree(), 2.5);
+}
+
+#[test]
+fn test_known_interior() {
+    // The cardioid's cusp, its far side, and the middles of both
+    assert!(Mandelbrot.known_interior(c(0.25, 0.0)));
+    assert!(Mandelbrot.known_interior(c(-0.74, 0.0)));
+    assert!(Mandelbrot.known_interior(c(0.0, 0.0)));
+    assert!(Mandelbrot.known_interior(c(-1.0, 0.0)));
+    assert!(Mandelbrot.known_interior(c(-1.0, 0.24)));
+
+    // Just outside each, and inside the set but in neither
+    assert!(!Mandelbrot.known_interior(c(0.26, 0.0)));
+    assert!(!Mandelbrot.known_interior(c(-1.0, 0.26)));
+    assert!(!Mandelbrot.known_interior(c(-0.12, 0.75)));
+    assert!(!Tricorn.known_interior(c(0.0, 0.0)));
+}
+
+#[test]
+fn test_parse_fractal() {
+    for name in ["mandelbrot", "julia:-0.8,0.156", "burning-ship", "tricorn",
+                 "multibrot:3", "multibrot:2.5"].iter() {
+        assert!(parse_fractal(name).is_some(), "couldn't parse {}", name);
+    }
+
+    assert!(parse_fractal("julia").is_none());
+    assert!(parse_fractal("julia:1").is_none());
+    assert!(parse_fractal("multibrot:1").is_none());
+    assert!(parse_fractal("multibrot:x").is_none());
+    assert!(parse_fractal("mandelbrot:2").is_none());
+    assert!(parse_fractal("newton").is_none());
+}
@@ -0,0 +1,12 @@
+// Everything apart from main and its options lives here, so that the
+// benchmarks can use it too
+
+pub mod animation;
+pub mod deep;
+pub mod escape;
+pub mod fractal;
+pub mod output;
+pub mod palette;
+pub mod parse;
+pub mod sampling;
+pub mod view;
@@ -1,192 +1,889 @@
 use num::Complex;
-use std::str::FromStr;
-use image::ColorType;
-use image::png::PNGEncoder;
-use std::fs::File;
+use std::collections::BTreeMap;
 use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+use mandlebrot::animation::*;
+use mandlebrot::deep::*;
+use mandlebrot::escape::*;
+use mandlebrot::fractal::*;
+use mandlebrot::output::*;
+use mandlebrot::palette::*;
+use mandlebrot::parse::*;
+use mandlebrot::sampling::*;
+use mandlebrot::view::*;
+
+/// Each pixel is three bytes: red, green and blue.
+const CHANNELS: usize = 3;
+
+/// The most iterations to try before deciding a point is in the set, unless
+/// told otherwise. Plenty for a view of the whole set, and the least that
+/// `auto_limit` picks.
+const DEFAULT_LIMIT: usize = 255;
+
+/// How many more iterations `auto_limit` allows for each time the view is
+/// ten times narrower. Points close to the set take longer to escape, and a
+/// deeper zoom looks at closer points.
+const LIMIT_PER_DECADE: f64 = 250.0;
+
+/// Images are handed out to the worker threads this many rows at a time:
+/// few enough that a slow strip through the set doesn't leave the other
+/// threads idle at the end, and enough that handing them out is cheap.
+const STRIP_ROWS: usize = 4;
+
+/// How many strips each worker thread may be handed ahead of the one being
+/// waited for. Enough to keep them busy past a slow strip, while keeping
+/// the strips held in memory at once to a handful, however big the image.
+const STRIPS_AHEAD: usize = 4;
+
+/// Pixels closer together than this on the complex plane are past what
+/// `f64` can draw, and need a deep zoom.
+const DEEP_ZOOM_SPACING: f64 = 1e-12;
+
+/// How wide a view given by its centre is at a magnification of 1: enough
+/// for the whole Mandelbrot set, which `--width 4` also gives.
+const FULL_WIDTH: f64 = 4.0;
+
+/// How far from square, as a fraction, pixels can be before corners given
+/// on the command line get a warning that the image will come out stretched.
+const ASPECT_TOLERANCE: f64 = 0.01;
+
+/// The most samples `--samples` can ask for along each side of a pixel. A
+/// square of 256 x 256 is already far past where more makes any difference.
+const MAX_SAMPLES: usize = 256;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 5 {
-        eprintln!("Usage: {} FILE PIXELS UPPERLEFT LOWERRIGHT",
-                  args[0]);
-        eprintln!("Example: {} mandel.png 1000x750 -1.20,0.35 -1,0.20",
-                  args[0]);
-        std::process::exit(1);
-    }
-
-    let bounds = parse_pair(&args[2], 'x')
-        .expect("error parsing image dimensions");
-    let upper_left = parse_complex(&args[3])
-        .expect("error parsing upper left corner point");
-    let lower_right = parse_complex(&args[4])
-        .expect("error parsing lower right corner point");
-
-    let mut pixels = vec![0; bounds.0 * bounds.1];
-
-    let threads = 8;
-    let rows_per_band = bounds.1 / threads + 1;
-
-    {
-        let bands: Vec<&mut [u8]> =
-            pixels.chunks_mut(rows_per_band * bounds.0).collect();
-        crossbeam::scope(|spawner| {
-            for (i, band) in bands.into_iter().enumerate() {
-                let top = rows_per_band * i;
-                let height = band.len() / bounds.0;
-                let band_bounds = (bounds.0, height);
-                let band_upper_left =
-                    pixel_to_point(bounds, (0, top), upper_left, lower_right);
-                let band_lower_right =
-                    pixel_to_point(bounds, (bounds.0, top + height),
-                                upper_left, lower_right);
-
-                spawner.spawn(move |_| {
-                    render(band, band_bounds, band_upper_left, band_lower_right);
-                });
+    let options = match parse_options(&args[1..]) {
+        Ok(options) if options.positional.len() == positional_count(&options) => options,
+        Ok(_) => {
+            print_usage(&args[0]);
+            std::process::exit(1);
+        }
+        Err(e) => usage_error(&args[0], &e)
+    };
+    let program = &args[0];
+    let args = &options.positional;
+
+    let bounds = parse_bounds(&args[1]).unwrap_or_else(|e| usage_error(program, &e));
+    let start = match options.centre.clone() {
+        Some(centre) => View::from_centre(bounds, centre, options.width, options.rotation),
+        None => {
+            let corner = |s: &str| parse_pair::<Decimal>(s, ',').unwrap_or_else(|| {
+                usage_error(program, &format!("a corner should be a point like -1.20,0.35, \
+                                               not '{}'", s))
+            });
+            let view = View::from_corners(bounds, &corner(&args[2]), &corner(&args[3]));
+            if (view.aspect() - 1.0).abs() > ASPECT_TOLERANCE {
+                eprintln!("Warning: these corners make each pixel {:.3} times as wide as it is \
+                           tall, so the image will be stretched; use --centre and --width \
+                           to keep pixels square", view.aspect());
+            }
+            view
+        }
+    };
+
+    let views = match options.frames {
+        None => vec![start],
+        Some(frames) => {
+            let target = options.target.clone().unwrap_or_else(|| start.centre.clone());
+            zoom_views(&start, &target, options.zoom.unwrap_or(1.0), frames)
+        }
+    };
+
+    let fractal = options.fractal.as_ref();
+    if !fractal.perturbable() && views.iter().any(|view| is_deep(options.deep, view)) {
+        if options.deep == Deep::On {
+            eprintln!("Error: only the Mandelbrot set can be drawn as a deep zoom");
+            std::process::exit(1);
+        }
+        eprintln!("Warning: this is too deep a zoom to draw properly, except for \
+                   the Mandelbrot set");
+    }
+
+    let mut gif = None;
+    if options.frames.is_some() && args[0].ends_with(".gif") {
+        gif = match Gif::create(&args[0], bounds) {
+            Ok(gif) => Some(gif),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    // A GIF needs each frame whole, to pick its colours. Anything else is
+    // written a strip at a time, as it's drawn.
+    let mut whole = Vec::new();
+    let mut image = None;
+    render_all(bounds, &views, &options, |frame, top, strip| {
+        let last = top + STRIP_ROWS >= bounds.1;
+        if let Some(gif) = &mut gif {
+            whole.resize(bounds.0 * bounds.1 * CHANNELS, 0);
+            let offset = top * bounds.0 * CHANNELS;
+            whole[offset..offset + strip.len()].copy_from_slice(strip);
+            if last {
+                gif.add_frame(&whole).expect("error writing GIF file");
             }
-        }).unwrap();
+            return;
+        }
+
+        if top == 0 {
+            let path = match options.frames {
+                Some(frames) => frame_path(&args[0], frame, frames),
+                None => args[0].clone()
+            };
+            image = match StripWriter::create(&path, bounds) {
+                Ok(image) => Some(image),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            };
+        }
+        let writer = image.as_mut().unwrap();
+        writer.write_rows(strip).expect("error writing image file");
+        if last {
+            image.take().unwrap().finish().expect("error writing image file");
+        }
+    });
+}
+
+/// How to draw one image.
+struct Plan<'a> {
+    view: &'a View,
+    /// The centre of `view`, to the precision of `f64`.
+    centre: Complex<f64>,
+    fractal: &'a dyn Fractal,
+    /// The most iterations to try for each point.
+    limit: usize,
+    /// The escape time the palette starts from. The fewest iterations any
+    /// point in a deep zoom takes can run into the thousands, and measuring
+    /// from zero would leave all of them much the same colour. Zero unless
+    /// the view is a deep zoom, or deep enough that `auto_limit` raised the
+    /// limit, so that other images are coloured just as they always were.
+    floor: f64,
+    /// For a deep zoom, the reference orbit to draw it around.
+    deep: Option<Perturbation>,
+}
+
+/// Work out how to draw `view` at the size given by `bounds`, which for a
+/// deep zoom means computing its reference orbit.
+fn plan<'a>(bounds: (usize, usize), view: &'a View, options: &'a Options) -> Plan<'a> {
+    let fractal = options.fractal.as_ref();
+    let limit = match options.iterations {
+        Iterations::Auto => auto_limit(bounds, view),
+        Iterations::Fixed(limit) => limit
+    };
+    let deep = if fractal.perturbable() && is_deep(options.deep, view) {
+        let spacing = view.spacing.0.abs().min(view.spacing.1.abs());
+        Some(Perturbation::new(&view.centre, spacing, limit))
+    } else {
+        None
+    };
+    let mut plan = Plan { view, centre: view.centre_point(), fractal, limit, floor: 0.0, deep };
+    let zoomed = options.iterations == Iterations::Auto && limit > DEFAULT_LIMIT;
+    if plan.deep.is_none() && !zoomed {
+        return plan;
     }
 
-    write_image(&args[1], &pixels, bounds)
-        .expect("error writing PNG file");
+    // The edges of the image are as far from the set as the image goes, if
+    // it's anywhere near the middle, so start the palette from the fastest
+    // escape among the corners and the middle of each side
+    let (w, h) = (bounds.0 as f64, bounds.1 as f64);
+    plan.floor = [(0.0, 0.0), (w / 2.0, 0.0), (w, 0.0), (0.0, h / 2.0),
+                  (w, h / 2.0), (0.0, h), (w / 2.0, h), (w, h)].iter()
+        .filter_map(|&sample| plan.escape_time(bounds, sample))
+        .fold(f64::INFINITY, f64::min);
+    if !plan.floor.is_finite() {
+        plan.floor = 0.0;
+    }
+    plan
 }
 
-/// Parse the string `s` as a coordinate pair, like `"400x600"` or `"1.0,0.5"`.
-///
-/// Specifically, `s` should have the form <left><sep><right>, where <sep> is
-/// the character given by the `separator` argument, and <left> and <right> are both
-/// strings that can be parsed by `T::from_str`.
+impl Plan<'_> {
+    /// The escape time at `sample`, a (column, row) position in an image
+    /// with dimensions `bounds` which needn't be a whole pixel.
+    fn escape_time(&self, bounds: (usize, usize), sample: (f64, f64)) -> Option<f64> {
+        let offset = self.view.offset(bounds, sample);
+        match self.deep {
+            Some(ref perturbation) => perturbation.escape_time(offset, self.limit),
+            None => escape_time(self.fractal, self.centre + offset, self.limit)
+        }
+    }
+}
+
+/// The iteration limit for `view` at the size given by `bounds`: the
+/// default for a view of the whole set, and `LIMIT_PER_DECADE` more for each
+/// time narrower than that it is.
+fn auto_limit(bounds: (usize, usize), view: &View) -> usize {
+    let width = view.spacing.0.abs() * bounds.0 as f64;
+    let decades = (FULL_WIDTH / width).log10().max(0.0);
+    DEFAULT_LIMIT + (decades * LIMIT_PER_DECADE).round() as usize
+}
+
+#[test]
+fn test_auto_limit() {
+    let centred = |width| View::from_centre((400, 300), parse_pair("-0.75,0.1", ',').unwrap(),
+                                            width, 0.0);
+    assert_eq!(auto_limit((400, 300), &centred(FULL_WIDTH)), DEFAULT_LIMIT);
+    assert_eq!(auto_limit((400, 300), &centred(10.0)), DEFAULT_LIMIT);
+    assert_eq!(auto_limit((400, 300), &centred(0.04)), DEFAULT_LIMIT + 500);
+    assert_eq!(auto_limit((400, 300), &centred(4e-50)), DEFAULT_LIMIT + 12500);
+    // The same for any image size
+    assert_eq!(auto_limit((40, 30), &View::from_centre((40, 30), centred(0.04).centre, 0.04, 0.0)),
+               DEFAULT_LIMIT + 500);
+}
+
+#[test]
+fn test_iteration_limit() {
+    // Just above the neck between the cardioid and the period 2 bulb, where
+    // points take about pi / 0.001 iterations to escape
+    let view = View::from_centre((8, 8), parse_pair("-0.75,0.001", ',').unwrap(), 1e-5, 0.0);
+    let escape = |iterations: &str| {
+        let options = parse_options(&strings(&["--iterations", iterations])).unwrap();
+        let plan = plan((8, 8), &view, &options);
+        (plan.limit, plan.escape_time((8, 8), (4.0, 4.0)), plan.floor)
+    };
+    assert_eq!(escape("255"), (255, None, 0.0));
+    let (limit, time, floor) = escape("100000");
+    assert_eq!(limit, 100000);
+    let time = time.unwrap();
+    assert!(time > 3000.0 && time < 3300.0, "escaped after {}", time);
+    // A limit that's asked for leaves the palette where it always was
+    assert_eq!(floor, 0.0);
+
+    // Ten times further from the neck, it only takes about 314. Zoomed in
+    // this far, the automatic limit is raised, and the palette starts from
+    // the fastest escape at the edges.
+    let view = View::from_centre((8, 8), parse_pair("-0.75,0.01", ',').unwrap(), 1e-5, 0.0);
+    let options = parse_options(&strings(&["--iterations", "auto"])).unwrap();
+    let zoomed = plan((8, 8), &view, &options);
+    let time = zoomed.escape_time((8, 8), (4.0, 4.0)).unwrap();
+    assert!(zoomed.limit > DEFAULT_LIMIT);
+    assert!(zoomed.floor > 300.0 && zoomed.floor <= time, "floor of {}", zoomed.floor);
+
+    // A view of the whole set isn't zoomed, so it's coloured as always
+    let view = View::from_centre((8, 8), parse_pair("-0.75,0", ',').unwrap(), FULL_WIDTH, 0.0);
+    assert_eq!(plan((8, 8), &view, &options).floor, 0.0);
+}
+
+/// Render the rows from `top` of an image with dimensions `bounds`, as
+/// `plan` and `sampling` say, into a new buffer of `height` rows.
+fn render_band(plan: &Plan, bounds: (usize, usize), top: usize, height: usize,
+               sampling: &Sampling, palette: &Palette) -> Vec<u8> {
+    let mut pixels = vec![0; bounds.0 * height * CHANNELS];
+    render(&mut pixels, bounds, top, plan, sampling, palette);
+    pixels
+}
+
+/// Render each of `views` in turn at the size given by `bounds`, handing
+/// each strip of `STRIP_ROWS` rows to `finished` along with the index of
+/// its image and its top row. The strips come in order, top to bottom and
+/// image by image, each as soon as it and all those before it are done.
 ///
-/// If `s` has the proper form, return `Some<(x, y)>`. If it doesn't parse
-/// correctly, return `None`.
-fn parse_pair<T: FromStr>(s: &str, separator: char) -> Option<(T, T)> {
-    match s.find(separator) {
-        None => None,
-        Some(index) => {
-            match (T::from_str(&s[..index]), T::from_str(&s[index + 1..])) {
-                (Ok(l), Ok(r)) => Some((l, r)),
-                _ => None
+/// The same `options.threads` worker threads draw every image, taking the
+/// next strip from a shared queue whenever they finish one, so they all
+/// keep busy however long each strip takes. Only `STRIPS_AHEAD` strips per
+/// thread are queued past the one being waited for, so only those are ever
+/// held in memory, and once the last strip of an image is queued, this
+/// thread works out the plan for the next while the workers draw.
+fn render_all<F>(bounds: (usize, usize), views: &[View], options: &Options,
+                 mut finished: F)
+    where F: FnMut(usize, usize, &[u8])
+{
+    let palette = &options.palette;
+    let sampling = &options.sampling;
+
+    crossbeam::scope(|spawner| {
+        let (strips, queue) = crossbeam::channel::unbounded::<(usize, Arc<Plan>, usize)>();
+        let (done, results) = crossbeam::channel::unbounded();
+
+        for _ in 0..options.threads {
+            let (queue, done) = (queue.clone(), done.clone());
+            spawner.spawn(move |_| {
+                for (index, plan, top) in queue {
+                    let height = STRIP_ROWS.min(bounds.1 - top);
+                    // A panic is handed on like any other result, as the
+                    // main thread would otherwise wait for this strip forever
+                    let pixels = panic::catch_unwind(AssertUnwindSafe(|| {
+                        render_band(&plan, bounds, top, height, sampling, palette)
+                    }));
+                    if done.send((index, pixels)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        // Only the workers can send results, so if they all stop, so does
+        // waiting for them
+        drop(done);
+
+        // Strips are numbered on from one image to the next
+        let per_image = bounds.1.div_ceil(STRIP_ROWS);
+        let total = per_image * views.len();
+        let ahead = options.threads * STRIPS_AHEAD;
+
+        let mut next = views.first().map(|view| Arc::new(plan(bounds, view, options)));
+        let mut current = None;
+        let mut queued = 0;
+        let mut waiting = BTreeMap::new();
+        for index in 0..total {
+            while queued < total && queued <= index + ahead {
+                let (frame, strip) = (queued / per_image, queued % per_image);
+                if strip == 0 {
+                    current = next.take();
+                }
+                let current = Arc::clone(current.as_ref().unwrap());
+                strips.send((queued, current, strip * STRIP_ROWS)).unwrap();
+                queued += 1;
+
+                if strip + 1 == per_image {
+                    next = views.get(frame + 1).map(|view| Arc::new(plan(bounds, view, options)));
+                }
             }
+
+            while !waiting.contains_key(&index) {
+                let (done, pixels) = results.recv().expect("render threads stopped");
+                waiting.insert(done, pixels.unwrap_or_else(|e| panic::resume_unwind(e)));
+            }
+            let pixels = waiting.remove(&index).unwrap();
+            finished(index / per_image, index % per_image * STRIP_ROWS, &pixels);
         }
-    }
+
+        // Let the workers know there's nothing more to come
+        drop(strips);
+    }).unwrap();
+}
+
+#[test]
+fn test_render_all() {
+    let corner = |s: &str| parse_pair::<Decimal>(s, ',').unwrap();
+    // Not a whole number of strips
+    let bounds = (30, 4 * STRIP_ROWS + 3);
+    let views = vec![View::from_corners(bounds, &corner("-2,1.2"), &corner("1,-1.2")),
+                     View::from_corners(bounds, &corner("-0.8,0.2"), &corner("-0.7,0.1"))];
+
+    let render_with = |threads: &str| {
+        let options = parse_options(&strings(&["--threads", threads])).unwrap();
+        let mut images = Vec::new();
+        render_all(bounds, &views, &options, |frame, top, strip| {
+            // In order, top to bottom and image by image
+            if top == 0 {
+                assert_eq!(frame, images.len());
+                images.push(Vec::new());
+            }
+            assert_eq!(frame + 1, images.len());
+            let image: &mut Vec<u8> = images.last_mut().unwrap();
+            assert_eq!(top * bounds.0 * CHANNELS, image.len());
+            image.extend_from_slice(strip);
+        });
+        images
+    };
+
+    let single = render_with("1");
+    assert_eq!(single.len(), 2);
+    assert_eq!(single[1].len(), bounds.0 * bounds.1 * CHANNELS);
+    assert_ne!(single[0], single[1]);
+    assert_eq!(single, render_with("3"));
+
+    // And the same as rendering the whole image in one go
+    let options = parse_options(&[]).unwrap();
+    let mut whole = vec![0; bounds.0 * bounds.1 * CHANNELS];
+    render(&mut whole, bounds, 0, &plan(bounds, &views[0], &options),
+           &Sampling::single(), &options.palette);
+    assert_eq!(single[0], whole);
 }
 
 #[test]
-fn test_parse_pair() {
-    assert_eq!(parse_pair::<i32>("",        ','), None);
-    assert_eq!(parse_pair::<i32>("10,",     ','), None);
-    assert_eq!(parse_pair::<i32>(",10",     ','), None);
-    assert_eq!(parse_pair::<i32>("10,20",   ','), Some((10, 20)));
-    assert_eq!(parse_pair::<i32>("10,20xy", ','), None);
-    assert_eq!(parse_pair::<f64>("0.5x",    'x'), None);
-    assert_eq!(parse_pair::<f64>("0.5x1.5", 'x'), Some((0.5, 1.5)));
+#[should_panic(expected = "divide by zero")]
+fn test_render_all_passes_on_panics() {
+    // No pixels in a row makes every strip divide by zero. Only two of the
+    // threads get a strip, and the rest must not leave it waiting for them.
+    let corner = |s: &str| parse_pair::<Decimal>(s, ',').unwrap();
+    let bounds = (0, 2 * STRIP_ROWS);
+    let views = vec![View::from_corners(bounds, &corner("-2,1"), &corner("2,-1"))];
+    let options = parse_options(&strings(&["--threads", "4"])).unwrap();
+    render_all(bounds, &views, &options, |_, _, _| {});
+}
+
+/// Show how to use the program and what was wrong with how it was run,
+/// then give up.
+fn usage_error(program: &str, error: &str) -> ! {
+    print_usage(program);
+    eprintln!("Error: {}", error);
+    std::process::exit(1);
 }
 
-/// Parse a pair of floating-point numbers separated by a comma as a complex
-/// number.
-fn parse_complex(s: &str) -> Option<Complex<f64>> {
-    match parse_pair(s, ',') {
-        Some((re, im)) => Some(Complex { re, im }),
-        None => None
+/// Parse image dimensions like `1000x750`, which can't be zero, or so big
+/// that the pixels of a whole image can't be counted.
+fn parse_bounds(s: &str) -> Result<(usize, usize), String> {
+    match parse_pair::<usize>(s, 'x') {
+        Some((width, height)) if width == 0 || height == 0 => {
+            Err(format!("an image can't be {} pixels", s))
+        }
+        Some((width, height)) => match width.checked_mul(height)
+                                             .and_then(|n| n.checked_mul(CHANNELS)) {
+            Some(_) => Ok((width, height)),
+            None => Err(format!("an image {} pixels is too big", s))
+        }
+        None => Err(format!("PIXELS should be a size like 1000x750, not '{}'", s))
     }
 }
 
 #[test]
-fn test_parse_complex() {
-    assert_eq!(parse_complex("1.25,-0.0625"),
-               Some(Complex { re: 1.25, im: -0.0625 }));
-    assert_eq!(parse_complex(",-0.0625"), None);
+fn test_parse_bounds() {
+    assert_eq!(parse_bounds("1000x750"), Ok((1000, 750)));
+    for bad in ["0x10", "10x0", "10", "10x", "-10x10", "10x10x10",
+                "18446744073709551615x2", "4294967296x4294967296"].iter() {
+        assert!(parse_bounds(bad).is_err(), "parsed '{}'", bad);
+    }
 }
 
-/// Given the row and column of a pixel in the output image, return the
-/// corresponding point on the complex plane.
-///
-/// `bounds` is a pair giving the width and height of the image in pixels.
-/// `pixel` is a (column, row) pair indicating a particular pixel in that image.
-/// The `upper_left` and `lower_right` parameters are points on the complex
-/// plane designating the area our image covers.
-fn pixel_to_point(bounds: (usize, usize),
-                  pixel: (usize, usize),
-                  upper_left: Complex<f64>,
-                  lower_right: Complex<f64>)
-    -> Complex<f64>
-{
-    let (width, height) = (lower_right.re - upper_left.re,
-                           upper_left.im - lower_right.im);
-    Complex {
-        re: upper_left.re + pixel.0 as f64 * width  / bounds.0 as f64,
-        im: upper_left.im - pixel.1 as f64 * height / bounds.1 as f64
-        // Why subtraction here? pixel.1 increases as we go down,
-        // but the imaginary component increases as we go up.
+fn print_usage(program: &str) {
+    eprintln!("Usage: {} [--fractal NAME] [--palette NAME|FILE] [--deep auto|on|off] \
+               [--iterations N|auto] [--threads N] [--samples N [--pattern grid|jitter] [--adaptive DIFF]] \
+               [--frames N [--target RE,IM] [--zoom FACTOR]] \
+               FILE PIXELS UPPERLEFT LOWERRIGHT",
+              program);
+    eprintln!("   or: {} [OPTIONS] --centre RE,IM [--width W|--magnification M] \
+               [--rotate DEGREES] FILE PIXELS",
+              program);
+    eprintln!("Example: {} mandel.png 1000x750 -1.20,0.35 -1,0.20",
+              program);
+    eprintln!("         {} mandel.png 1000x750 --centre -1.1,0.275 --width 0.2",
+              program);
+    eprintln!("Corners are stretched to fit PIXELS, with a warning. A view given by");
+    eprintln!("its centre always has square pixels: W across (default {}), or {}/M,",
+              FULL_WIDTH, FULL_WIDTH);
+    eprintln!("and turned anticlockwise by DEGREES.");
+    eprintln!("Palettes: {} (default grey), or a file with one",
+              BUILT_IN.join(", "));
+    eprintln!("          #rrggbb colour per line");
+    eprintln!("Fractals: mandelbrot (default), julia:RE,IM, burning-ship, tricorn,");
+    eprintln!("          or multibrot:D for z^D + c with any D above 1");
+    eprintln!("Iterations: the most to try for each point before counting it as in");
+    eprintln!("the set. By default {} for the whole set, and {} more each time the",
+              DEFAULT_LIMIT, LIMIT_PER_DECADE);
+    eprintln!("view is ten times narrower.");
+    eprintln!("Threads: one per CPU by default.");
+    eprintln!("Supersampling: each pixel is the average of N x N samples, with N up to");
+    eprintln!("{}, on a grid or jittered within it. With --adaptive, only pixels", MAX_SAMPLES);
+    eprintln!("whose colour differs from a neighbour's by more than DIFF (0 to 255)");
+    eprintln!("are supersampled.");
+    eprintln!("Deep zooms, which need more precision than f64, are drawn by");
+    eprintln!("perturbation when the pixels are close enough together, or always");
+    eprintln!("with --deep on. Only the Mandelbrot set can be drawn this way.");
+    eprintln!("With --frames, renders an animation zooming in from the view given by");
+    eprintln!("FACTOR (default 1), on the target (default the middle of the view).");
+    eprintln!("It's written as an animated GIF if FILE ends in .gif, or else as");
+    eprintln!("numbered files: zoom.png becomes zoom-000.png, zoom-001.png...");
+    eprintln!("Images are written as TIFF if FILE ends in .tif or .tiff, and as PNG");
+    eprintln!("otherwise, a few rows at a time as they're drawn, so even images too");
+    eprintln!("big to hold in memory can be drawn.");
+}
+
+/// How many iterations to try before deciding a point is in the set.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Iterations {
+    /// Picked for each view by `auto_limit`.
+    Auto,
+    Fixed(usize),
+}
+
+/// When to draw a deep zoom, with `deep::Perturbation`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Deep {
+    Auto,
+    On,
+    Off,
+}
+
+/// The options given on the command line, and everything else in order.
+struct Options {
+    fractal: Box<dyn Fractal>,
+    palette: Palette,
+    deep: Deep,
+    iterations: Iterations,
+    frames: Option<usize>,
+    target: Option<(Decimal, Decimal)>,
+    zoom: Option<f64>,
+    threads: usize,
+    sampling: Sampling,
+    centre: Option<(Decimal, Decimal)>,
+    width: f64,
+    rotation: f64,
+    positional: Vec<String>,
+}
+
+/// How many positional arguments to expect: the file and the image size,
+/// and the corners unless the view is given by its centre.
+fn positional_count(options: &Options) -> usize {
+    if options.centre.is_some() { 2 } else { 4 }
+}
+
+/// Pick out the options (which all take a value, as `--name value`) from
+/// the command line arguments, not including the program name.
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        fractal: Box::new(Mandelbrot),
+        palette: Palette::built_in("grey").unwrap(),
+        deep: Deep::Auto,
+        iterations: Iterations::Auto,
+        frames: None,
+        target: None,
+        zoom: None,
+        threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        sampling: Sampling::single(),
+        centre: None,
+        width: FULL_WIDTH,
+        rotation: 0.0,
+        positional: Vec::new(),
+    };
+    let mut framed = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        // Negative numbers like -1.2,0.3 are positional, not options
+        if !arg.starts_with("--") {
+            options.positional.push(arg.clone());
+            continue;
+        }
+
+        let value = iter.next()
+            .ok_or_else(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--fractal" => {
+                options.fractal = parse_fractal(value)
+                    .ok_or_else(|| format!("unknown fractal '{}'", value))?;
+            }
+            "--palette" => options.palette = load_palette(value)?,
+            "--deep" => {
+                options.deep = match value.as_str() {
+                    "auto" => Deep::Auto,
+                    "on" => Deep::On,
+                    "off" => Deep::Off,
+                    _ => return Err(format!("--deep should be auto, on or off, not '{}'",
+                                            value))
+                };
+            }
+            "--iterations" => {
+                options.iterations = match value.as_str() {
+                    "auto" => Iterations::Auto,
+                    _ => match value.parse() {
+                        Ok(limit) if limit > 0 => Iterations::Fixed(limit),
+                        _ => return Err(format!("--iterations should be auto or a number of \
+                                                 iterations, not '{}'", value))
+                    }
+                };
+            }
+            "--frames" => {
+                options.frames = match value.parse() {
+                    Ok(frames) if frames > 0 => Some(frames),
+                    _ => return Err(format!("--frames should be a number of frames, not '{}'",
+                                            value))
+                };
+            }
+            "--target" => {
+                options.target = Some(parse_pair(value, ',')
+                    .ok_or_else(|| format!("--target should be a point like -0.75,0.1, \
+                                            not '{}'", value))?);
+            }
+            "--samples" => {
+                options.sampling.per_side = match value.parse() {
+                    Ok(per_side) if per_side > 0 && per_side <= MAX_SAMPLES => per_side,
+                    _ => return Err(format!("--samples should be a number of samples \
+                                             along each side of a pixel, from 1 to {}, \
+                                             not '{}'", MAX_SAMPLES, value))
+                };
+            }
+            "--pattern" => {
+                options.sampling.pattern = match value.as_str() {
+                    "grid" => Pattern::Grid,
+                    "jitter" => Pattern::Jitter,
+                    _ => return Err(format!("--pattern should be grid or jitter, not '{}'",
+                                            value))
+                };
+            }
+            "--adaptive" => {
+                options.sampling.threshold = Some(value.parse().map_err(|_| {
+                    format!("--adaptive should be a difference in colour from 0 to 255, \
+                             not '{}'", value)
+                })?);
+            }
+            "--threads" => {
+                options.threads = match value.parse() {
+                    Ok(threads) if threads > 0 => threads,
+                    _ => return Err(format!("--threads should be a number of threads, not '{}'",
+                                            value))
+                };
+            }
+            "--zoom" => {
+                options.zoom = match value.parse::<f64>() {
+                    Ok(zoom) if zoom > 0.0 && zoom.is_finite() => Some(zoom),
+                    _ => return Err(format!("--zoom should be a positive number, not '{}'",
+                                            value))
+                };
+            }
+            "--centre" => {
+                options.centre = Some(parse_pair(value, ',')
+                    .ok_or_else(|| format!("--centre should be a point like -0.75,0.1, \
+                                            not '{}'", value))?);
+            }
+            "--width" | "--magnification" => {
+                let number = match value.parse::<f64>() {
+                    Ok(number) if number > 0.0 && number.is_finite() => number,
+                    _ => return Err(format!("{} should be a positive number, not '{}'",
+                                            arg, value))
+                };
+                options.width = if arg == "--width" { number } else { FULL_WIDTH / number };
+                framed = true;
+            }
+            "--rotate" => {
+                options.rotation = match value.parse::<f64>() {
+                    Ok(degrees) if degrees.is_finite() => degrees.to_radians(),
+                    _ => return Err(format!("--rotate should be an angle in degrees, not '{}'",
+                                            value))
+                };
+                framed = true;
+            }
+            _ => return Err(format!("unknown option '{}'", arg))
+        }
     }
+
+    if framed && options.centre.is_none() {
+        return Err(String::from("--width, --magnification and --rotate go with --centre"));
+    }
+
+    if options.frames.is_none() && (options.target.is_some() || options.zoom.is_some()) {
+        return Err(String::from("--target and --zoom are for animations, with --frames"));
+    }
+
+    Ok(options)
+}
+
+#[cfg(test)]
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|s| s.to_string()).collect()
 }
 
 #[test]
-fn test_pixel_to_point() {
-    assert_eq!(pixel_to_point((100, 100), (25, 75),
-                              Complex { re: -1.0, im:  1.0 },
-                              Complex { re:  1.0, im: -1.0 }),
-               Complex { re: -0.5, im: -0.5 });
+fn test_parse_options() {
+    let options = parse_options(&strings(&["out.png", "--palette", "fire",
+                                           "10x10", "-1,1", "1,-1"])).unwrap();
+    assert_eq!(options.positional, strings(&["out.png", "10x10", "-1,1", "1,-1"]));
+    assert_eq!(options.palette, Palette::built_in("fire").unwrap());
+    assert_eq!(options.fractal.degree(), 2.0);
+    assert_eq!(options.deep, Deep::Auto);
+    assert_eq!(options.iterations, Iterations::Auto);
+
+    let options = parse_options(&strings(&["--fractal", "multibrot:3",
+                                           "--deep", "off", "--iterations", "2000000"])).unwrap();
+    assert_eq!(options.fractal.degree(), 3.0);
+    assert_eq!(options.deep, Deep::Off);
+    assert_eq!(options.iterations, Iterations::Fixed(2_000_000));
+    assert_eq!(options.frames, None);
+    assert!(options.threads > 0);
+    assert_eq!(options.sampling, Sampling::single());
+
+    let options = parse_options(&strings(&["--samples", "3", "--pattern", "jitter",
+                                           "--adaptive", "20"])).unwrap();
+    assert_eq!(options.sampling,
+               Sampling { per_side: 3, pattern: Pattern::Jitter, threshold: Some(20) });
+
+    let options = parse_options(&strings(&["--frames", "90", "--zoom", "1e6",
+                                           "--target", "-0.75,0.1"])).unwrap();
+    assert_eq!(options.frames, Some(90));
+    assert_eq!(options.zoom, Some(1e6));
+    assert_eq!(options.target, parse_pair("-0.75,0.1", ','));
+
+    let options = parse_options(&strings(&["out.png", "--centre", "-0.75,0.1",
+                                           "--magnification", "100", "--rotate", "90",
+                                           "10x10"])).unwrap();
+    assert_eq!(options.centre, parse_pair("-0.75,0.1", ','));
+    assert_eq!(options.width, 0.04);
+    assert_eq!(options.rotation, std::f64::consts::FRAC_PI_2);
+    assert_eq!(positional_count(&options), options.positional.len());
+
+    let options = parse_options(&strings(&["--centre", "0,0", "--width", "1e-30"])).unwrap();
+    assert_eq!(options.width, 1e-30);
+    assert_eq!(options.rotation, 0.0);
+
+    assert!(parse_options(&strings(&["--palette"])).is_err());
+    assert!(parse_options(&strings(&["--palette", "/no/such/palette"])).is_err());
+    assert!(parse_options(&strings(&["--colour", "red"])).is_err());
+    assert!(parse_options(&strings(&["--fractal", "julia"])).is_err());
+    assert!(parse_options(&strings(&["--deep", "maybe"])).is_err());
+    assert!(parse_options(&strings(&["--iterations", "0"])).is_err());
+    assert!(parse_options(&strings(&["--iterations", "lots"])).is_err());
+    assert!(parse_options(&strings(&["--frames", "0"])).is_err());
+    assert!(parse_options(&strings(&["--frames", "9", "--zoom", "-2"])).is_err());
+    assert!(parse_options(&strings(&["--zoom", "10"])).is_err());
+    assert!(parse_options(&strings(&["--threads", "0"])).is_err());
+    assert!(parse_options(&strings(&["--samples", "0"])).is_err());
+    assert!(parse_options(&strings(&["--samples", "257"])).is_err());
+    assert!(parse_options(&strings(&["--pattern", "poisson"])).is_err());
+    assert!(parse_options(&strings(&["--adaptive", "256"])).is_err());
+    assert!(parse_options(&strings(&["--centre", "0"])).is_err());
+    assert!(parse_options(&strings(&["--centre", "0,0", "--width", "0"])).is_err());
+    assert!(parse_options(&strings(&["--centre", "0,0", "--rotate", "left"])).is_err());
+    assert!(parse_options(&strings(&["--width", "2"])).is_err());
+    assert!(parse_options(&strings(&["--rotate", "45"])).is_err());
 }
 
-/// Render a rectangle of the Mandelbrot set into a buffer of pixels.
+/// Render rows of an image into a buffer of pixels.
 ///
-/// The `bounds` argument gives the width and height of the buffer `pixels`,
-/// which holds one grayscale pixel per byte. The `upper_left` and `lower_right`
-/// arguments specify points on the complex plane corresponding to the upper-
-/// left and lower-right corners of the pixel buffer.
+/// The `bounds` argument gives the width and height of the whole image,
+/// and `pixels` holds one RGB pixel per three bytes for its rows starting at
+/// row `top`. The `plan` argument says what to draw, and `sampling` how many
+/// points on the complex plane to average for each pixel.
 fn render(pixels: &mut [u8],
     bounds: (usize, usize),
-    upper_left: Complex<f64>,
-    lower_right: Complex<f64>) {
-    assert!(pixels.len() == bounds.0 * bounds.1);
+    top: usize,
+    plan: &Plan,
+    sampling: &Sampling,
+    palette: &Palette) {
+    assert!(pixels.len().is_multiple_of(bounds.0 * CHANNELS));
+    let height = pixels.len() / (bounds.0 * CHANNELS);
+
+    let colour = |pixel: (usize, usize), offset: (f64, f64)| {
+        let sample = (pixel.0 as f64 + offset.0, pixel.1 as f64 + offset.1);
+        shade(plan.escape_time(bounds, sample), plan.floor, plan.limit, palette)
+    };
+    let supersampled = |pixel| {
+        let mut average = Average::default();
+        for sample in 0..sampling.count() {
+            average.add(colour(pixel, sampling.offset(pixel, sample)));
+        }
+        average.colour()
+    };
+
+    // For adaptive sampling, first take a single sample for every pixel, and
+    // for the rows either side as well, for their neighbours
+    let first = top.saturating_sub(1);
+    let last = (top + height + 1).min(bounds.1);
+    let mut rough = Vec::new();
+    if sampling.threshold.is_some() {
+        for row in first..last {
+        for column in 0..bounds.0 {
+            rough.push(colour((column, row), (0.0, 0.0)));
+        }
+        }
+    }
+    let rough_at = |column: usize, row: usize| rough[(row - first) * bounds.0 + column];
 
-    for row in 0..bounds.1 {
+    for row in top..top + height {
     for column in 0..bounds.0 {
-        let point = pixel_to_point(bounds, (column, row),
-                                    upper_left, lower_right);
-        pixels[row * bounds.0 + column] =
-            match escape_time(point, 255) {
-                None => 0,
-                Some(count) => 255 - count as u8
-            };
+        let pixel_colour = match sampling.threshold {
+            None => supersampled((column, row)),
+            Some(threshold) => {
+                let here = rough_at(column, row);
+                let edge = (row.saturating_sub(1)..(row + 2).min(last)).any(|r| {
+                    (column.saturating_sub(1)..(column + 2).min(bounds.0))
+                        .any(|c| differs(here, rough_at(c, r), threshold))
+                });
+                if edge { supersampled((column, row)) } else { here }
+            }
+        };
+        let offset = ((row - top) * bounds.0 + column) * CHANNELS;
+        pixels[offset..offset + CHANNELS].copy_from_slice(&pixel_colour);
     }
     }
 }
 
-/// Try to determine if `c` is in the Mandelbrot set, using at most `limit`
-/// iterations to decide.
-///
-/// If `c` is not a member, return `Some(i)`, where `i` is the number of
-/// iterations it took for `c` to leave the circle of radius two centered on the
-/// origin. If `c` seems to be a member (more precisely, if we reached the
-/// iteration limit without being able to prove that `c` is not a member),
-/// return `None`.
-fn escape_time(c: Complex<f64>, limit: usize) -> Option<usize> {
-    let mut z = Complex { re: 0.0, im: 0.0 };
-    for i in 0..limit {
-        if z.norm_sqr() > 4.0 {
-            return Some(i);
+#[test]
+fn test_supersampling() {
+    let corner = |s: &str| parse_pair::<Decimal>(s, ',').unwrap();
+    let bounds = (40, 30);
+    let view = View::from_corners(bounds, &corner("-0.8,0.2"), &corner("-0.7,0.1"));
+    let options = parse_options(&[]).unwrap();
+    let plan = plan(bounds, &view, &options);
+
+    let image = |sampling: Sampling| {
+        let mut pixels = vec![0; bounds.0 * bounds.1 * CHANNELS];
+        render(&mut pixels, bounds, 0, &plan, &sampling, &options.palette);
+        pixels.chunks(CHANNELS).map(|p| [p[0], p[1], p[2]]).collect::<Vec<_>>()
+    };
+    let grid = Sampling { per_side: 4, ..Sampling::single() };
+    let single = image(Sampling::single());
+    let supersampled = image(grid);
+    let adaptive = image(Sampling { threshold: Some(8), ..grid });
+
+    assert_ne!(single, supersampled);
+    assert_ne!(supersampled, image(Sampling { pattern: Pattern::Jitter, ..grid }));
+    assert_eq!(single, image(Sampling { threshold: Some(255), ..grid }));
+
+    // Adaptive sampling only supersamples some of the pixels
+    let mut refined = 0;
+    for i in 0..single.len() {
+        if adaptive[i] == supersampled[i] && adaptive[i] != single[i] {
+            refined += 1;
+        } else {
+            assert_eq!(adaptive[i], single[i]);
         }
-        z = z * z + c;
     }
+    assert!(refined > 0 && refined < single.len() / 2, "refined {} pixels", refined);
+}
 
-    None
+/// Whether to draw `view` as a deep zoom: if `deep` says so, or if it's
+/// left to us and `needs_deep_zoom` says so.
+fn is_deep(deep: Deep, view: &View) -> bool {
+    match deep {
+        Deep::On => true,
+        Deep::Off => false,
+        Deep::Auto => needs_deep_zoom(view)
+    }
 }
 
-/// Write the buffer `pixels`, whose dimensions are given by `bounds`, to the
-/// file named `filename`.
-fn write_image(filename: &str, pixels: &[u8], bounds: (usize, usize))
-    -> Result<(), std::io::Error>
-{
-    let output = File::create(filename)?;
+/// Whether the pixels of `view` are too close together to draw with `f64`.
+fn needs_deep_zoom(view: &View) -> bool {
+    view.spacing.0.abs().min(view.spacing.1.abs()) < DEEP_ZOOM_SPACING
+}
+
+#[test]
+fn test_needs_deep_zoom() {
+    let corner = |s: &str| parse_pair::<Decimal>(s, ',').unwrap();
+    let at = |bounds, upper_left, lower_right| {
+        needs_deep_zoom(&View::from_corners(bounds, &corner(upper_left), &corner(lower_right)))
+    };
+    assert!(!at((100, 100), "-2,1", "1,-1"));
+    assert!(!at((1000, 1000), "-0.75,0.1", "-0.74999999,0.09999999"));
+    assert!(at((1000, 1000), "-0.75,0.1", "-0.7499999999,0.0999999999"));
+    assert!(at((10, 10), "0.3,0", "0.3,0"));
+    // Past where f64 can tell the corners apart
+    assert!(at((10, 10), "0.3,0", "0.30000000000000000001,-1e-20"));
 
-    let encoder = PNGEncoder::new(output);
-    encoder.encode(&pixels,
-                   bounds.0 as u32, bounds.1 as u32,
-                   ColorType::Gray(8))?;
+    let centred = |width| View::from_centre((1000, 500), corner("-0.75,0.1"), width, 0.0);
+    assert!(!needs_deep_zoom(&centred(1e-6)));
+    assert!(needs_deep_zoom(&centred(1e-10)));
+}
 
-    Ok(())
-}
\ No newline at end of file
+/// The colour for a point with the given escape time: black for points in
+/// the set, otherwise from the palette, which runs from `floor` iterations
+/// to `limit`. Escape times are spread out on a log scale, as most points
+/// escape within the first few iterations past the floor.
+fn shade(escape: Option<f64>, floor: f64, limit: usize, palette: &Palette) -> [u8; 3] {
+    match escape {
+        None => [0, 0, 0],
+        Some(mu) => {
+            let range = (limit as f64 - floor).max(1.0);
+            palette.colour((1.0 + (mu - floor).max(0.0)).ln() / (1.0 + range).ln())
+        }
+    }
+}
+
+#[test]
+fn test_shade() {
+    let grey = Palette::built_in("grey").unwrap();
+    assert_eq!(shade(None, 0.0, 255, &grey), [0, 0, 0]);
+    assert_eq!(shade(Some(0.0), 0.0, 255, &grey), [255, 255, 255]);
+    assert_eq!(shade(Some(255.0), 0.0, 255, &grey), [0, 0, 0]);
+    assert_eq!(shade(Some(15.0), 0.0, 255, &grey), [128, 128, 128]);
+
+    // The same range of colours, however many iterations it starts from
+    assert_eq!(shade(Some(5000.0), 5000.0, 5255, &grey), [255, 255, 255]);
+    assert_eq!(shade(Some(5015.0), 5000.0, 5255, &grey), [128, 128, 128]);
+    assert_eq!(shade(Some(4000.0), 5000.0, 5255, &grey), [255, 255, 255]);
+}
@@ -0,0 +1,253 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// An image file being written a strip of rows at a time, from the top
+/// down, so that only the strip being written has to be in memory however
+/// big the image is.
+pub enum StripWriter {
+    Png(Box<png::StreamWriter<'static, BufWriter<File>>>),
+    Tiff(Tiff),
+}
+
+impl StripWriter {
+    /// Start writing an image with dimensions `bounds` to `path`: a TIFF if
+    /// its name ends in .tif or .tiff, and otherwise a PNG.
+    pub fn create(path: &str, bounds: (usize, usize)) -> Result<StripWriter, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("failed to create '{}': {}", path, e))?;
+        let file = BufWriter::new(file);
+        let failed = |e: std::io::Error| format!("failed to write '{}': {}", path, e);
+
+        let lower = path.to_lowercase();
+        if lower.ends_with(".tif") || lower.ends_with(".tiff") {
+            return Ok(StripWriter::Tiff(Tiff::start(file, bounds).map_err(failed)?));
+        }
+
+        let too_big = || format!("a PNG can't be more than {} pixels across", u32::MAX);
+        let mut encoder = png::Encoder::new(file,
+                                            u32::try_from(bounds.0).map_err(|_| too_big())?,
+                                            u32::try_from(bounds.1).map_err(|_| too_big())?);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let stream = encoder.write_header()
+            .and_then(|writer| writer.into_stream_writer())
+            .map_err(|e| failed(e.into()))?;
+        Ok(StripWriter::Png(Box::new(stream)))
+    }
+
+    /// Write the next rows of the image, one RGB pixel per three bytes.
+    pub fn write_rows(&mut self, pixels: &[u8]) -> Result<(), std::io::Error> {
+        match self {
+            StripWriter::Png(stream) => stream.write_all(pixels),
+            StripWriter::Tiff(tiff) => tiff.write_rows(pixels)
+        }
+    }
+
+    /// Finish the file off, once every row has been written.
+    pub fn finish(self) -> Result<(), std::io::Error> {
+        match self {
+            StripWriter::Png(stream) => Ok(stream.finish()?),
+            StripWriter::Tiff(tiff) => tiff.finish()
+        }
+    }
+}
+
+/// The largest file a plain TIFF can be, as its offsets are 32 bits. Past
+/// this it has to be a BigTIFF, whose offsets are 64 bits.
+const TIFF_MAX_SIZE: u64 = u32::MAX as u64;
+
+/// An uncompressed RGB TIFF, with one strip per row.
+///
+/// The size of the pixel data is known from the start, so it goes straight
+/// after the header, and the directory of tags describing it at the end.
+pub struct Tiff {
+    file: BufWriter<File>,
+    bounds: (usize, usize),
+    /// Whether it's a BigTIFF.
+    big: bool,
+    /// Where the directory goes, after the pixels.
+    directory: u64,
+    /// How many bytes of pixels have been written so far.
+    written: u64,
+}
+
+/// The TIFF field types used here, with their codes and sizes in bytes.
+const SHORT: (u16, usize) = (3, 2);
+const LONG: (u16, usize) = (4, 4);
+const LONG8: (u16, usize) = (16, 8);
+
+impl Tiff {
+    fn start(mut file: BufWriter<File>, bounds: (usize, usize)) -> Result<Tiff, std::io::Error> {
+        let data = (bounds.0 * bounds.1 * 3) as u64;
+        // Leave plenty of room for the directory, which holds two offsets
+        // for each row
+        let big = data + 64 * bounds.1 as u64 + 4096 > TIFF_MAX_SIZE;
+
+        // Little-endian, then the version, then where the directory starts:
+        // straight after the pixels, rounded up to a whole word
+        let header = if big { 16 } else { 8 };
+        let directory = (header + data).next_multiple_of(8);
+        file.write_all(b"II")?;
+        if big {
+            file.write_all(&43u16.to_le_bytes())?;
+            file.write_all(&8u16.to_le_bytes())?;
+            file.write_all(&0u16.to_le_bytes())?;
+            file.write_all(&directory.to_le_bytes())?;
+        } else {
+            file.write_all(&42u16.to_le_bytes())?;
+            file.write_all(&(directory as u32).to_le_bytes())?;
+        }
+
+        Ok(Tiff { file, bounds, big, directory, written: 0 })
+    }
+
+    fn write_rows(&mut self, pixels: &[u8]) -> Result<(), std::io::Error> {
+        self.written += pixels.len() as u64;
+        self.file.write_all(pixels)
+    }
+
+    fn finish(mut self) -> Result<(), std::io::Error> {
+        let (width, height) = self.bounds;
+        let row = (width * 3) as u64;
+        if self.written != row * height as u64 {
+            return Err(std::io::Error::other("the image isn't finished"));
+        }
+
+        let header: u64 = if self.big { 16 } else { 8 };
+        let directory = self.directory;
+        let padding = directory - header - self.written;
+        self.file.write_all(&vec![0; padding as usize])?;
+
+        let offset = if self.big { LONG8 } else { LONG };
+        let rows: Vec<u64> = (0..height as u64).map(|y| header + y * row).collect();
+        let fields: Vec<(u16, (u16, usize), Vec<u64>)> = vec![
+            (256, LONG, vec![width as u64]),    // ImageWidth
+            (257, LONG, vec![height as u64]),   // ImageLength
+            (258, SHORT, vec![8, 8, 8]),        // BitsPerSample
+            (259, SHORT, vec![1]),              // Compression: none
+            (262, SHORT, vec![2]),              // PhotometricInterpretation: RGB
+            (273, offset, rows),                // StripOffsets
+            (277, SHORT, vec![3]),              // SamplesPerPixel
+            (278, LONG, vec![1]),               // RowsPerStrip
+            (279, LONG, vec![row; height]),     // StripByteCounts
+            (284, SHORT, vec![1]),              // PlanarConfiguration: interleaved
+        ];
+
+        // Each field's values go in the directory entry if they fit, and
+        // otherwise after the directory, with the entry saying where
+        let (count_size, inline) = if self.big { (8, 8) } else { (2, 4) };
+        let entry_size = 4 + 2 * inline;
+        let mut extra_at = directory + (count_size + fields.len() * entry_size + inline) as u64;
+        let mut entries = Vec::new();
+        let mut extra = Vec::new();
+        write_uint(&mut entries, fields.len() as u64, count_size);
+        for (tag, (kind, size), values) in &fields {
+            let mut bytes = Vec::new();
+            for &value in values {
+                write_uint(&mut bytes, value, *size);
+            }
+            entries.extend_from_slice(&tag.to_le_bytes());
+            entries.extend_from_slice(&kind.to_le_bytes());
+            write_uint(&mut entries, values.len() as u64, inline);
+            if bytes.len() <= inline {
+                bytes.resize(inline, 0);
+                entries.extend_from_slice(&bytes);
+            } else {
+                write_uint(&mut entries, extra_at, inline);
+                extra_at += bytes.len() as u64;
+                extra.extend_from_slice(&bytes);
+            }
+        }
+        // There's no next directory
+        write_uint(&mut entries, 0, inline);
+
+        self.file.write_all(&entries)?;
+        self.file.write_all(&extra)?;
+        self.file.flush()
+    }
+}
+
+/// Append the lowest `size` bytes of `value` to `bytes`, little-endian.
+fn write_uint(bytes: &mut Vec<u8>, value: u64, size: usize) {
+    bytes.extend_from_slice(&value.to_le_bytes()[..size]);
+}
+
+#[cfg(test)]
+fn test_image(bounds: (usize, usize)) -> Vec<u8> {
+    (0..bounds.0 * bounds.1 * 3).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+#[test]
+fn test_png_strips() {
+    let bounds = (7, 5);
+    let pixels = test_image(bounds);
+    let path = std::env::temp_dir().join(format!("mandlebrot-strips-{}.png", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    let mut writer = StripWriter::create(path, bounds).unwrap();
+    for strip in pixels.chunks(2 * bounds.0 * 3) {
+        writer.write_rows(strip).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let decoder = png::Decoder::new(File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut read = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut read).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!((info.width, info.height), (7, 5));
+    assert_eq!(info.color_type, png::ColorType::Rgb);
+    assert_eq!(read, pixels);
+}
+
+#[test]
+fn test_tiff_strips() {
+    let bounds = (7, 5);
+    let pixels = test_image(bounds);
+    let path = std::env::temp_dir().join(format!("mandlebrot-strips-{}.tiff", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    let mut writer = StripWriter::create(path, bounds).unwrap();
+    writer.write_rows(&pixels[..bounds.0 * 3]).unwrap();
+    assert!(matches!(writer, StripWriter::Tiff(_)));
+    writer.write_rows(&pixels[bounds.0 * 3..]).unwrap();
+    writer.finish().unwrap();
+
+    let file = std::fs::read(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    let u16_at = |at: usize| u16::from_le_bytes([file[at], file[at + 1]]) as usize;
+    let u32_at = |at: usize| u32::from_le_bytes([file[at], file[at + 1], file[at + 2], file[at + 3]])
+        as usize;
+
+    // A little-endian TIFF with the pixels straight after the header
+    assert_eq!(&file[..4], b"II*\0");
+    assert_eq!(&file[8..8 + pixels.len()], &pixels[..]);
+
+    // Look up a field's first value in the directory
+    let directory = u32_at(4);
+    let field = |tag: usize| {
+        (0..u16_at(directory))
+            .map(|i| directory + 2 + i * 12)
+            .find(|&entry| u16_at(entry) == tag)
+            .map(|entry| if u16_at(entry + 2) == 3 && u32_at(entry + 4) == 1 {
+                u16_at(entry + 8)
+            } else {
+                u32_at(entry + 8)
+            })
+    };
+    assert_eq!(field(256), Some(7));
+    assert_eq!(field(257), Some(5));
+    assert_eq!(field(262), Some(2));
+    assert_eq!(field(277), Some(3));
+    // The strip offsets don't fit in the entry, so it says where they are
+    let offsets = field(273).unwrap();
+    assert_eq!((0..5).map(|y| u32_at(offsets + 4 * y)).collect::<Vec<_>>(),
+               vec![8, 29, 50, 71, 92]);
+
+    // Missing rows are an error, not a short file
+    let mut writer = StripWriter::create(path, bounds).unwrap();
+    writer.write_rows(&pixels[..bounds.0 * 3]).unwrap();
+    assert!(writer.finish().is_err());
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,134 @@
+use std::fs;
+
+/// A colour gradient: evenly spaced RGB stops, blended linearly. Position
+/// 0.0 is the first stop and 1.0 the last.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Palette {
+    stops: Vec<[u8; 3]>,
+}
+
+/// The names of the built-in palettes, as accepted by `--palette`.
+pub const BUILT_IN: [&str; 5] = ["grey", "fire", "ice", "ultra", "rainbow"];
+
+impl Palette {
+    pub fn new(stops: Vec<[u8; 3]>) -> Option<Palette> {
+        if stops.len() < 2 {
+            return None;
+        }
+        Some(Palette { stops })
+    }
+
+    /// One of the palettes in `BUILT_IN`.
+    pub fn built_in(name: &str) -> Option<Palette> {
+        let stops: &[[u8; 3]] = match name {
+            // Fast escapes are white and points near the set black, like the
+            // old 8-bit greyscale images.
+            "grey" => &[[255, 255, 255], [0, 0, 0]],
+            "fire" => &[[255, 255, 224], [255, 200, 40], [230, 80, 0],
+                        [120, 0, 0], [0, 0, 0]],
+            "ice" => &[[255, 255, 255], [150, 220, 255], [20, 110, 200],
+                       [0, 20, 80], [0, 0, 0]],
+            // The classic blue and gold
+            "ultra" => &[[0, 7, 100], [32, 107, 203], [237, 255, 255],
+                         [255, 170, 0], [0, 2, 0]],
+            "rainbow" => &[[255, 0, 0], [255, 255, 0], [0, 255, 0],
+                           [0, 255, 255], [0, 0, 255], [255, 0, 255]],
+            _ => return None
+        };
+        Palette::new(stops.to_vec())
+    }
+
+    /// The colour at `t`, which is clamped to the range 0.0 to 1.0.
+    pub fn colour(&self, t: f64) -> [u8; 3] {
+        let segments = self.stops.len() - 1;
+        let position = t.clamp(0.0, 1.0) * segments as f64;
+        let i = (position as usize).min(segments - 1);
+        let fraction = position - i as f64;
+
+        let (from, to) = (self.stops[i], self.stops[i + 1]);
+        let mut colour = [0; 3];
+        for c in 0..3 {
+            let blended = from[c] as f64 + (to[c] as f64 - from[c] as f64) * fraction;
+            colour[c] = blended.round() as u8;
+        }
+        colour
+    }
+}
+
+/// Parse a colour written as `#rrggbb` (the `#` is optional).
+fn parse_colour(s: &str) -> Option<[u8; 3]> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Parse a palette file: one colour per line in the order they should
+/// appear, as `#rrggbb`. Blank lines and lines starting with `//` are
+/// ignored.
+pub fn parse_palette(text: &str) -> Result<Palette, String> {
+    let mut stops = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        match parse_colour(line) {
+            Some(colour) => stops.push(colour),
+            None => return Err(format!("line {}: '{}' isn't a colour like #ff8800",
+                                       number + 1, line))
+        }
+    }
+
+    Palette::new(stops)
+        .ok_or_else(|| String::from("a palette needs at least two colours"))
+}
+
+/// A built-in palette by name, or else a palette file.
+pub fn load_palette(name: &str) -> Result<Palette, String> {
+    if let Some(palette) = Palette::built_in(name) {
+        return Ok(palette);
+    }
+
+    let text = fs::read_to_string(name)
+        .map_err(|e| format!("'{}' isn't one of {} and can't be read as a file: {}",
+                             name, BUILT_IN.join(", "), e))?;
+    parse_palette(&text).map_err(|e| format!("{}: {}", name, e))
+}
+
+#[test]
+fn test_built_in_palettes() {
+    for name in BUILT_IN.iter() {
+        assert!(Palette::built_in(name).is_some(), "{} is missing", name);
+    }
+    assert_eq!(Palette::built_in("plaid"), None);
+}
+
+#[test]
+fn test_palette_colour() {
+    let grey = Palette::built_in("grey").unwrap();
+    assert_eq!(grey.colour(0.0),  [255, 255, 255]);
+    assert_eq!(grey.colour(0.5),  [128, 128, 128]);
+    assert_eq!(grey.colour(1.0),  [0, 0, 0]);
+    assert_eq!(grey.colour(-3.0), [255, 255, 255]);
+    assert_eq!(grey.colour(7.0),  [0, 0, 0]);
+
+    let three = Palette::new(vec![[0, 0, 0], [200, 100, 0], [0, 0, 200]]).unwrap();
+    assert_eq!(three.colour(0.25), [100, 50, 0]);
+    assert_eq!(three.colour(0.5),  [200, 100, 0]);
+    assert_eq!(three.colour(0.75), [100, 50, 100]);
+}
+
+#[test]
+fn test_parse_palette() {
+    let palette = parse_palette("// sunset\n#ff0000\n\n  ff8800 \n#000040\n").unwrap();
+    assert_eq!(palette, Palette::new(vec![[255, 0, 0], [255, 136, 0], [0, 0, 64]]).unwrap());
+
+    assert!(parse_palette("#ff0000\n").is_err());
+    assert!(parse_palette("#ff0000\nred\n").is_err());
+    assert!(parse_palette("#ff0000\n#ff00\n").is_err());
+}
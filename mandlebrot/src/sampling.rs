@@ -0,0 +1,135 @@
+/// Where the samples go within a pixel.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Pattern {
+    /// Evenly spaced in rows and columns.
+    Grid,
+    /// One at random in each square of the grid, which trades the grid's
+    /// regular patterns of error for less noticeable noise.
+    Jitter,
+}
+
+/// How many points to look at for each pixel, and which pixels to look at
+/// more than one for. Each pixel is the average colour of its samples.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Sampling {
+    /// Samples along each side of a pixel, so `per_side` squared in all.
+    pub per_side: usize,
+    pub pattern: Pattern,
+    /// If set, pixels are only supersampled if their colour differs from
+    /// one of their neighbours' by more than this in any channel, going by
+    /// a single sample each. Elsewhere the single sample is used.
+    pub threshold: Option<u8>,
+}
+
+impl Sampling {
+    /// One sample per pixel, right on its point.
+    pub fn single() -> Sampling {
+        Sampling { per_side: 1, pattern: Pattern::Grid, threshold: None }
+    }
+
+    /// The number of samples for a supersampled pixel.
+    pub fn count(&self) -> usize {
+        self.per_side * self.per_side
+    }
+
+    /// Where to take sample number `sample` for the pixel at (column, row),
+    /// as an offset in pixels from the pixel's own point. The samples cover
+    /// the square a pixel wide centred on it.
+    pub fn offset(&self, pixel: (usize, usize), sample: usize) -> (f64, f64) {
+        let n = self.per_side as f64;
+        let (column, row) = ((sample % self.per_side) as f64, (sample / self.per_side) as f64);
+        let (dx, dy) = match self.pattern {
+            Pattern::Grid => (0.5, 0.5),
+            Pattern::Jitter => jitter(pixel, sample)
+        };
+        ((column + dx) / n - 0.5, (row + dy) / n - 0.5)
+    }
+}
+
+/// A point in the unit square that looks random, but is the same every
+/// time for the same pixel and sample, so that images can be reproduced
+/// and don't depend on how the work was split up.
+fn jitter(pixel: (usize, usize), sample: usize) -> (f64, f64) {
+    // SplitMix64, seeded with everything that identifies the sample
+    let mut state = (pixel.0 as u64)
+        .wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (pixel.1 as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+        ^ (sample as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        // The top 53 bits, as a fraction
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    };
+    (next(), next())
+}
+
+/// Adds up colours to find their average.
+#[derive(Default)]
+pub struct Average {
+    total: [u64; 3],
+    count: u64,
+}
+
+impl Average {
+    pub fn add(&mut self, colour: [u8; 3]) {
+        for (total, &channel) in self.total.iter_mut().zip(colour.iter()) {
+            *total += channel as u64;
+        }
+        self.count += 1;
+    }
+
+    /// The average of the colours added so far, rounded to the nearest.
+    pub fn colour(&self) -> [u8; 3] {
+        self.total.map(|total| ((total + self.count / 2) / self.count) as u8)
+    }
+}
+
+/// Whether `a` and `b` differ by more than `threshold` in any channel.
+pub fn differs(a: [u8; 3], b: [u8; 3], threshold: u8) -> bool {
+    a.iter().zip(b.iter()).any(|(&a, &b)| a.abs_diff(b) > threshold)
+}
+
+#[test]
+fn test_grid_offsets() {
+    let single = Sampling::single();
+    assert_eq!(single.count(), 1);
+    assert_eq!(single.offset((3, 4), 0), (0.0, 0.0));
+
+    let grid = Sampling { per_side: 2, ..Sampling::single() };
+    let offsets: Vec<_> = (0..grid.count()).map(|k| grid.offset((3, 4), k)).collect();
+    assert_eq!(offsets, vec![(-0.25, -0.25), (0.25, -0.25), (-0.25, 0.25), (0.25, 0.25)]);
+}
+
+#[test]
+fn test_jittered_offsets() {
+    let jittered = Sampling { per_side: 3, pattern: Pattern::Jitter, threshold: None };
+
+    for &pixel in [(0, 0), (1, 0), (0, 1), (120, 7)].iter() {
+        for k in 0..jittered.count() {
+            // In its own ninth of the pixel, and the same every time
+            let (x, y) = jittered.offset(pixel, k);
+            let (column, row) = ((k % 3) as f64, (k / 3) as f64);
+            assert!(x >= column / 3.0 - 0.5 && x < (column + 1.0) / 3.0 - 0.5);
+            assert!(y >= row / 3.0 - 0.5 && y < (row + 1.0) / 3.0 - 0.5);
+            assert_eq!((x, y), jittered.offset(pixel, k));
+        }
+    }
+    assert_ne!(jittered.offset((0, 0), 0), jittered.offset((1, 0), 0));
+    assert_ne!(jittered.offset((0, 0), 0), jittered.offset((0, 1), 0));
+}
+
+#[test]
+fn test_average() {
+    let mut average = Average::default();
+    average.add([0, 255, 10]);
+    average.add([255, 255, 11]);
+    assert_eq!(average.colour(), [128, 255, 11]);
+
+    assert!(differs([0, 0, 0], [0, 0, 11], 10));
+    assert!(!differs([0, 0, 0], [10, 10, 10], 10));
+    assert!(differs([200, 0, 0], [0, 0, 0], 100));
+}
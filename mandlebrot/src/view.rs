@@ -0,0 +1,111 @@
+use num::Complex;
+use super::deep::Decimal;
+
+/// What part of the complex plane an image shows.
+#[derive(Debug, Clone)]
+pub struct View {
+    /// The point in the middle of the image, as a (real, imaginary) pair.
+    pub centre: (Decimal, Decimal),
+    /// How far apart neighbouring pixels are on the complex plane, across
+    /// and down. Unless they're the same, the image comes out stretched.
+    pub spacing: (f64, f64),
+    /// How far the image is turned anticlockwise about its centre, in
+    /// radians.
+    pub rotation: f64,
+}
+
+impl View {
+    /// The view of an image with dimensions `bounds` whose upper-left and
+    /// lower-right corners are at the given points, squashing or stretching
+    /// the image to fit.
+    pub fn from_corners(bounds: (usize, usize),
+                        upper_left: &(Decimal, Decimal),
+                        lower_right: &(Decimal, Decimal)) -> View {
+        let width = lower_right.0.minus(&upper_left.0).to_f64();
+        let height = upper_left.1.minus(&lower_right.1).to_f64();
+        View {
+            centre: (upper_left.0.plus(&lower_right.0).times(0.5),
+                     upper_left.1.plus(&lower_right.1).times(0.5)),
+            spacing: (width / bounds.0 as f64, height / bounds.1 as f64),
+            rotation: 0.0,
+        }
+    }
+
+    /// The view of an image with dimensions `bounds` centred on `centre`,
+    /// `width` across and turned by `rotation` radians, with square pixels.
+    pub fn from_centre(bounds: (usize, usize), centre: (Decimal, Decimal), width: f64,
+                       rotation: f64) -> View {
+        let spacing = width / bounds.0 as f64;
+        View { centre, spacing: (spacing, spacing), rotation }
+    }
+
+    /// How many times as wide as they are tall the pixels are on the complex
+    /// plane.
+    pub fn aspect(&self) -> f64 {
+        (self.spacing.0 / self.spacing.1).abs()
+    }
+
+    /// How far from the centre, on the complex plane, the (column, row)
+    /// position `pixel` is in an image with dimensions `bounds`. It needn't
+    /// be a whole pixel.
+    pub fn offset(&self, bounds: (usize, usize), pixel: (f64, f64)) -> Complex<f64> {
+        let across = (pixel.0 - bounds.0 as f64 / 2.0) * self.spacing.0;
+        // Rows go down the image, but the imaginary axis goes up
+        let up = (bounds.1 as f64 / 2.0 - pixel.1) * self.spacing.1;
+
+        if self.rotation == 0.0 {
+            return Complex { re: across, im: up };
+        }
+        let (sin, cos) = self.rotation.sin_cos();
+        Complex { re: across * cos - up * sin, im: across * sin + up * cos }
+    }
+
+    /// The centre as the nearest `f64`s.
+    pub fn centre_point(&self) -> Complex<f64> {
+        Complex { re: self.centre.0.to_f64(), im: self.centre.1.to_f64() }
+    }
+
+    /// The point on the complex plane at position `pixel`, as for `offset`,
+    /// to the precision of `f64`. Drawing finds the centre once and adds the
+    /// offsets itself, rather than converting it for every pixel.
+    #[cfg(test)]
+    pub fn point(&self, bounds: (usize, usize), pixel: (f64, f64)) -> Complex<f64> {
+        self.centre_point() + self.offset(bounds, pixel)
+    }
+}
+
+#[cfg(test)]
+fn pair(re: &str, im: &str) -> (Decimal, Decimal) {
+    (re.parse().unwrap(), im.parse().unwrap())
+}
+
+#[test]
+fn test_view_from_corners() {
+    let view = View::from_corners((100, 100), &pair("-1", "1"), &pair("1", "-1"));
+    assert_eq!(view.centre_point(), Complex { re: 0.0, im: 0.0 });
+    assert_eq!(view.spacing, (0.02, 0.02));
+    assert_eq!(view.aspect(), 1.0);
+
+    assert_eq!(view.point((100, 100), (25.0, 75.0)), Complex { re: -0.5, im: -0.5 });
+    assert_eq!(view.point((100, 100), (0.0, 0.0)), Complex { re: -1.0, im: 1.0 });
+    assert_eq!(view.point((100, 100), (100.0, 100.0)), Complex { re: 1.0, im: -1.0 });
+
+    // Twice as much of the plane across as down, in a square image
+    let stretched = View::from_corners((100, 100), &pair("-2", "1"), &pair("2", "-1"));
+    assert_eq!(stretched.aspect(), 2.0);
+}
+
+#[test]
+fn test_view_from_centre() {
+    let view = View::from_centre((200, 100), pair("-0.5", "0.25"), 4.0, 0.0);
+    assert_eq!(view.spacing, (0.02, 0.02));
+    assert_eq!(view.point((200, 100), (0.0, 0.0)), Complex { re: -2.5, im: 1.25 });
+    assert_eq!(view.point((200, 100), (100.0, 50.0)), Complex { re: -0.5, im: 0.25 });
+
+    // A quarter turn anticlockwise: right of the middle of the image is up
+    // on the plane, and below it is to the right
+    let turned = View::from_centre((200, 100), pair("0", "0"), 4.0, std::f64::consts::FRAC_PI_2);
+    let close = |a: Complex<f64>, b: Complex<f64>| (a - b).norm() < 1e-12;
+    assert!(close(turned.point((200, 100), (150.0, 50.0)), Complex { re: 0.0, im: 1.0 }));
+    assert!(close(turned.point((200, 100), (100.0, 100.0)), Complex { re: 1.0, im: 0.0 }));
+}
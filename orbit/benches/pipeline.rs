@@ -0,0 +1,138 @@
+// Benchmarks for each stage of a step, and for drawing and encoding a frame,
+// at a few different numbers of bodies. Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use webp_animation::Encoder;
+
+use orbit::kernel::*;
+use orbit::object::*;
+use orbit::render::*;
+use orbit::scene::*;
+
+const SPACE_SIZE: u32 = 1024;
+const BODY_COUNTS: [u32; 3] = [10, 1_000, 10_000];
+
+fn scene(num_objects: u32) -> Vec<Object> {
+    Scene {
+        space_size: SPACE_SIZE,
+        num_objects,
+        seed: 42,
+    }
+    .create()
+}
+
+fn bench_forces(c: &mut Criterion) {
+    let mut group = c.benchmark_group("forces");
+    // 10k bodies is 100 million pairs a step, so don't take too many samples
+    group.sample_size(10);
+
+    for n in BODY_COUNTS {
+        let objects = scene(n);
+
+        for (name, kernel) in [
+            ("reference", Kernel::Reference),
+            ("f64", Kernel::F64),
+            ("f32", Kernel::F32),
+        ] {
+            let physics = Physics {
+                kernel,
+                ..Physics::default()
+            };
+            group.bench_with_input(BenchmarkId::new(name, n), &objects, |b, objects| {
+                b.iter(|| calculate_forces_on_all(objects, &physics, 0.0))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_collisions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("collisions");
+    group.sample_size(10);
+
+    for n in BODY_COUNTS {
+        let objects = scene(n);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &objects, |b, objects| {
+            b.iter(|| {
+                collide_all(
+                    objects,
+                    Collisions::Merge,
+                    &mut next_free_id(objects),
+                    &mut Vec::new(),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_update_all(c: &mut Criterion) {
+    let physics = Physics::default();
+    let mut group = c.benchmark_group("update_all");
+    group.sample_size(10);
+
+    for n in BODY_COUNTS {
+        let objects = scene(n);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &objects, |b, objects| {
+            b.iter(|| {
+                update_all(
+                    objects,
+                    &physics,
+                    0.0,
+                    1.0,
+                    &mut next_free_id(objects),
+                    &mut Vec::new(),
+                    None,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_render(c: &mut Criterion) {
+    let camera = Camera::fit(SPACE_SIZE as f64, SPACE_SIZE as usize);
+    let mut group = c.benchmark_group("render");
+
+    for n in BODY_COUNTS {
+        let objects = scene(n);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &objects, |b, objects| {
+            b.iter(|| {
+                let mut frame = Frame::new(camera);
+                frame.render_all(objects, RenderMode::Circles, 8);
+                frame
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let camera = Camera::fit(SPACE_SIZE as f64, SPACE_SIZE as usize);
+    let mut group = c.benchmark_group("encode");
+    group.sample_size(10);
+
+    for n in BODY_COUNTS {
+        let mut frame = Frame::new(camera);
+        frame.render_all(&scene(n), RenderMode::Circles, 8);
+
+        group.bench_with_input(BenchmarkId::from_parameter(n), &frame, |b, frame| {
+            b.iter(|| {
+                let mut encoder = Encoder::new((SPACE_SIZE, SPACE_SIZE)).unwrap();
+                encoder.add_frame(frame.values(), 0).unwrap();
+                encoder.finalize(40).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_forces,
+    bench_collisions,
+    bench_update_all,
+    bench_render,
+    bench_encode
+);
+criterion_main!(benches);
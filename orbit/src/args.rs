@@ -0,0 +1,523 @@
+use super::batch::*;
+use super::field::*;
+use super::kernel::*;
+use super::object::*;
+use super::render::*;
+use super::simulation::*;
+use super::stop::*;
+
+use rand::prelude::*;
+use std::str::FromStr;
+use std::thread;
+use text_colorizer::*;
+
+// A minute per frame is already a slideshow, and keeps the animation's
+// timestamps, which are milliseconds in an i32, well clear of overflowing
+const MAX_FRAME_DELAY: u32 = 60_000;
+
+// Every option that takes a value, so that an unknown one is reported as such
+// rather than as missing its value
+const VALUE_OPTIONS: [&str; 28] = [
+    "-n",
+    "--objects",
+    "-i",
+    "--iterations",
+    "--dt",
+    "--seed",
+    "--until",
+    "-s",
+    "--substeps",
+    "--frames",
+    "--duration",
+    "--frame-delay",
+    "-o",
+    "--output",
+    "--svg",
+    "-f",
+    "--field",
+    "--pn",
+    "--kernel",
+    "--collisions",
+    "-m",
+    "--mode",
+    "--bin-size",
+    "--overlay",
+    "--sweep",
+    "-j",
+    "--jobs",
+    "--summary",
+];
+
+pub struct Arguments {
+    pub params: Params,
+    // No default, so that batch runs only write animations when asked to
+    pub output: Option<String>,
+    pub svg: Option<String>,
+    pub fields: Vec<Box<dyn ForceField>>,
+    pub speed_of_light: Option<f64>,
+    pub collisions: Collisions,
+    pub kernel: Kernel,
+    pub mode: RenderMode,
+    pub bin_size: usize,
+    pub overlay: Option<Corner>,
+    pub labels: bool,
+    pub tui: bool,
+    pub profile: bool,
+    // How often to render a frame, and how long each frame is shown for
+    pub frames: Frames,
+    pub frame_delay: u32,
+    pub until: Vec<Until>,
+    pub sweeps: Vec<Sweep>,
+    pub jobs: usize,
+    pub summary: Option<String>,
+    // Exactly what was asked for, to go in the manifest
+    pub command_line: Vec<String>,
+}
+
+impl Default for Arguments {
+    fn default() -> Self {
+        Arguments {
+            params: Params {
+                num_objects: 100,
+                iterations: 500,
+                dt: 1.0,
+                seed: thread_rng().gen(),
+            },
+            output: None,
+            svg: None,
+            fields: Vec::new(),
+            speed_of_light: None,
+            collisions: Collisions::Merge,
+            kernel: Kernel::Reference,
+            mode: RenderMode::Circles,
+            bin_size: 8,
+            overlay: None,
+            labels: false,
+            tui: false,
+            profile: false,
+            frames: Frames::Every(1),
+            frame_delay: 40,
+            until: Vec::new(),
+            sweeps: Vec::new(),
+            jobs: thread::available_parallelism().map_or(1, |n| n.get()),
+            summary: None,
+            command_line: Vec::new(),
+        }
+    }
+}
+
+impl Arguments {
+    pub fn physics(&self) -> Physics<'_> {
+        Physics {
+            fields: &self.fields,
+            speed_of_light: self.speed_of_light,
+            collisions: self.collisions,
+            kernel: self.kernel,
+        }
+    }
+
+    // Physics steps per rendered frame for a run of `params`, which may not
+    // be the base params if iterations are being swept over
+    pub fn substeps(&self, params: &Params) -> u32 {
+        match self.frames {
+            Frames::Every(substeps) => substeps,
+            Frames::Total(frames) => params.iterations.div_ceil(frames),
+        }
+    }
+}
+
+// Either a frame every so many steps, or a target number of frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Frames {
+    Every(u32),
+    Total(u32),
+}
+
+// What the command line asked for: either a run, or just the help text
+pub enum Command {
+    Run(Box<Arguments>),
+    Help,
+}
+
+pub fn print_usage() {
+    eprintln!("{} - simulate some bodies under gravity", "orbit".green());
+    eprintln!();
+    eprintln!("Usage: orbit [options]");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  -n, --objects <N>      number of bodies orbiting the sun (default 100)");
+    eprintln!("  -i, --iterations <N>   number of steps to simulate, at least 1 (default 500)");
+    eprintln!("      --dt <T>           simulated time per step (default 1)");
+    eprintln!("      --seed <N>         seed for the random scene (default random)");
+    eprintln!("      --until <COND>     stop early once COND holds, may be repeated to");
+    eprintln!("                         stop at the first of several (--iterations");
+    eprintln!("                         still applies): bodies:N, escape:BODY,");
+    eprintln!("                         escape:any, drift:EPSILON or time:T");
+    eprintln!("  -s, --substeps <N>     physics steps per rendered frame (default 1)");
+    eprintln!("      --frames <N>       render about N frames, choosing the substeps");
+    eprintln!("      --duration <SECS>  make the animation about SECS long, choosing");
+    eprintln!("                         the substeps");
+    eprintln!("      --frame-delay <MS> how long each frame is shown (default 40)");
+    eprintln!("  -o, --output <FILE>    animated WebP to write (default orbit.webp, or");
+    eprintln!("                         none for a batch)");
+    eprintln!("      --svg <FILE>       also plot the full trajectories to an SVG file");
+    eprintln!("  -f, --field <SPEC>     add an external force field, may be repeated");
+    eprintln!("      --pn <C>           add the post-Newtonian (relativistic) correction");
+    eprintln!("                         to gravity, with C the speed of light");
+    eprintln!("      --collisions <MODEL>");
+    eprintln!("                         what happens when bodies hit (default merge):");
+    eprintln!("                         merge, bounce, or fragment[:N] to shatter into");
+    eprintln!("                         N pieces (default 4) above escape velocity");
+    eprintln!("      --kernel <K>       how to sum gravity (default reference, the");
+    eprintln!("                         original), or f64 or f32 for the faster");
+    eprintln!("                         vectorised kernel in double or single precision");
+    eprintln!("  -m, --mode <MODE>      how to draw the bodies (default circles):");
+    eprintln!("                         circles, density (or density:mass),");
+    eprintln!("                         density:count or velocity");
+    eprintln!("      --bin-size <PX>    cell size for the density and velocity modes");
+    eprintln!("                         (default 8)");
+    eprintln!("      --overlay <CORNER> show step, time, body count and energy drift in a");
+    eprintln!("                         corner: top-left, top-right, bottom-left,");
+    eprintln!("                         bottom-right or off (default off)");
+    eprintln!("      --labels           write the name of each body next to it");
+    eprintln!("      --tui              watch the simulation live in the terminal instead");
+    eprintln!("                         (space pause, n step, +/- zoom, arrows pan,");
+    eprintln!("                         [/] speed, q quit)");
+    eprintln!("      --profile          print the time spent in each stage when done");
+    eprintln!("      --from-manifest <FILE>");
+    eprintln!("                         repeat the run described by a manifest; options");
+    eprintln!("                         after this one override it");
+    eprintln!("  -h, --help             show this message");
+    eprintln!();
+    eprintln!("Batch runs:");
+    eprintln!("      --sweep <NAME=VALUES>");
+    eprintln!("                         run once for every value of objects, iterations,");
+    eprintln!("                         dt or seed, given as a list (1,2,5) or a range");
+    eprintln!("                         (1..20); several sweeps run every combination");
+    eprintln!("  -j, --jobs <N>         runs to do at once (default number of CPUs)");
+    eprintln!("      --summary <FILE>   write the summary CSV here instead of stdout");
+    eprintln!("  Animations and SVGs are numbered per run, e.g. out-3.webp");
+    eprintln!();
+    eprintln!("Each run writes a manifest of how it was made next to its animation (or");
+    eprintln!("SVG), e.g. orbit.webp.json, which --from-manifest can reproduce it from.");
+    eprintln!();
+    eprintln!("Fields:");
+    eprintln!("  uniform:gx,gy          constant acceleration everywhere");
+    eprintln!("  point:x,y,mass         fixed point mass");
+    eprintln!("  halo:x,y,v0,core       logarithmic (galaxy halo) potential");
+    eprintln!("  drag:k, drag2:k        linear and quadratic drag");
+    eprintln!("  thrust:body,start,end,fx,fy");
+    eprintln!("                         push a body (e.g. body3) between two times");
+}
+
+fn parse_number<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, name))
+}
+
+fn parse_count(name: &str, value: &str) -> Result<u32, String> {
+    value.parse().map_err(|_| {
+        format!(
+            "invalid value '{}' for {}, expected a non-negative whole number",
+            value, name
+        )
+    })
+}
+
+/// Parse the command line (without the program name).
+///
+/// Options are either `--name value` or `--name=value`. Anything that isn't
+/// understood is an error rather than being silently ignored.
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let mut parsed = Arguments {
+        command_line: args.to_vec(),
+        ..Arguments::default()
+    };
+    let mut substeps = None;
+    let mut frames = None;
+    let mut duration: Option<f64> = None;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        let (name, inline) = match arg.find('=') {
+            Some(index) if arg.starts_with("--") => (&arg[..index], Some(&arg[index + 1..])),
+            _ => (arg.as_str(), None),
+        };
+
+        // Flags, which don't take a value
+        match name {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--labels" => {
+                parsed.labels = true;
+                continue;
+            }
+            "--tui" => {
+                parsed.tui = true;
+                continue;
+            }
+            "--profile" => {
+                parsed.profile = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        if !VALUE_OPTIONS.contains(&name) {
+            return Err(format!("unknown option '{}'", name));
+        }
+
+        let value = match inline {
+            Some(v) => v,
+            None => match iter.next() {
+                Some(v) => v.as_str(),
+                None => return Err(format!("missing value for {}", name)),
+            },
+        };
+
+        match name {
+            "-n" | "--objects" => parsed.params.num_objects = parse_count(name, value)?,
+            "-i" | "--iterations" => parsed.params.iterations = parse_count(name, value)?,
+            "--dt" => parsed.params.dt = parse_number(name, value)?,
+            "--seed" => parsed.params.seed = parse_number(name, value)?,
+            "--until" => match parse_until(value) {
+                Some(until) => parsed.until.push(until),
+                None => return Err(format!("can't understand stopping condition '{}'", value)),
+            },
+            "-s" | "--substeps" => substeps = Some(parse_count(name, value)?),
+            "--frames" => frames = Some(parse_count(name, value)?),
+            "--duration" => duration = Some(parse_number(name, value)?),
+            "--frame-delay" => parsed.frame_delay = parse_count(name, value)?,
+            "-o" | "--output" => parsed.output = Some(value.to_string()),
+            "--svg" => parsed.svg = Some(value.to_string()),
+            "-f" | "--field" => match parse_field(value) {
+                Some(field) => parsed.fields.push(field),
+                None => return Err(format!("can't understand field '{}'", value)),
+            },
+            "--pn" => parsed.speed_of_light = Some(parse_number(name, value)?),
+            "--kernel" => match parse_kernel(value) {
+                Some(kernel) => parsed.kernel = kernel,
+                None => return Err(format!("unknown kernel '{}'", value)),
+            },
+            "--collisions" => match parse_collisions(value) {
+                Some(collisions) => parsed.collisions = collisions,
+                None => return Err(format!("unknown collision model '{}'", value)),
+            },
+            "-m" | "--mode" => match parse_render_mode(value) {
+                Some(mode) => parsed.mode = mode,
+                None => return Err(format!("unknown render mode '{}'", value)),
+            },
+            "--bin-size" => parsed.bin_size = parse_count(name, value)? as usize,
+            "--overlay" if value == "off" => parsed.overlay = None,
+            "--overlay" => match parse_corner(value) {
+                Some(corner) => parsed.overlay = Some(corner),
+                None => return Err(format!("unknown corner '{}' for --overlay", value)),
+            },
+            "--sweep" => parsed.sweeps.push(parse_sweep(value)?),
+            "-j" | "--jobs" => parsed.jobs = parse_count(name, value)? as usize,
+            "--summary" => parsed.summary = Some(value.to_string()),
+            _ => return Err(format!("unknown option '{}'", name)),
+        }
+    }
+
+    if parsed.params.iterations == 0 {
+        return Err(String::from("--iterations must be at least 1"));
+    }
+    if !parsed.params.dt.is_finite() || parsed.params.dt <= 0.0 {
+        return Err(String::from("--dt must be positive and finite"));
+    }
+    if run_count(&parsed.sweeps).is_none_or(|runs| runs > MAX_RUNS) {
+        return Err(format!("a batch can have at most {} runs", MAX_RUNS));
+    }
+    if parsed.tui && !parsed.sweeps.is_empty() {
+        return Err(String::from("--tui can't be used with --sweep"));
+    }
+    if let Some(c) = parsed.speed_of_light {
+        if !c.is_finite() || c <= 0.0 {
+            return Err(String::from("--pn must be positive and finite"));
+        }
+    }
+    if parsed.jobs == 0 {
+        return Err(String::from("--jobs must be at least 1"));
+    }
+    if parsed.bin_size == 0 {
+        return Err(String::from("--bin-size must be at least 1"));
+    }
+    if parsed.frame_delay == 0 || parsed.frame_delay > MAX_FRAME_DELAY {
+        return Err(format!(
+            "--frame-delay must be between 1 and {}",
+            MAX_FRAME_DELAY
+        ));
+    }
+
+    // A target length is just another way of asking for a number of frames
+    let frames = match (frames, duration) {
+        (Some(_), Some(_)) => {
+            return Err(String::from(
+                "--frames and --duration can't be used together",
+            ))
+        }
+        (None, Some(secs)) if secs.is_nan() || secs <= 0.0 => {
+            return Err(String::from("--duration must be positive"))
+        }
+        (None, Some(secs)) => {
+            Some(((secs * 1000.0 / parsed.frame_delay as f64).round() as u32).max(1))
+        }
+        (frames, None) => frames,
+    };
+
+    parsed.frames = match (substeps, frames) {
+        (Some(_), Some(_)) => {
+            return Err(String::from(
+                "--substeps can't be used with --frames or --duration",
+            ))
+        }
+        (Some(0), None) => return Err(String::from("--substeps must be at least 1")),
+        (None, Some(0)) => return Err(String::from("--frames must be at least 1")),
+        (Some(n), None) => Frames::Every(n),
+        (None, Some(n)) => Frames::Total(n),
+        (None, None) => Frames::Every(1),
+    };
+
+    Ok(Command::Run(Box::new(parsed)))
+}
+
+#[cfg(test)]
+fn parse(args: &[&str]) -> Result<Arguments, String> {
+    let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+    match parse_args(&args)? {
+        Command::Run(parsed) => Ok(*parsed),
+        Command::Help => Err(String::from("help")),
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    let parsed = parse(&["-n", "20", "--iterations=30", "--field", "drag:0.1"]).unwrap();
+    assert_eq!(20, parsed.params.num_objects);
+    assert_eq!(30, parsed.params.iterations);
+    assert_eq!(None, parsed.output);
+    assert!(parsed.sweeps.is_empty());
+    assert_eq!(1, parsed.fields.len());
+    assert_eq!(None, parsed.speed_of_light);
+    assert_eq!(Some(50.0), parse(&["--pn", "50"]).unwrap().speed_of_light);
+    assert_eq!(Collisions::Merge, parsed.collisions);
+    assert!(parsed.until.is_empty());
+    assert_eq!(Kernel::Reference, parsed.kernel);
+    assert_eq!(Kernel::F64, parse(&["--kernel", "f64"]).unwrap().kernel);
+    assert_eq!(Kernel::F32, parse(&["--kernel", "f32"]).unwrap().kernel);
+    assert_eq!(
+        vec![Until::Bodies(3), Until::Time(100.0)],
+        parse(&["--until", "bodies:3", "--until=time:100"])
+            .unwrap()
+            .until
+    );
+    assert_eq!(
+        Collisions::Fragment(6),
+        parse(&["--collisions=fragment:6"]).unwrap().collisions
+    );
+    assert_eq!(None, parsed.overlay);
+    assert_eq!(Frames::Every(1), parsed.frames);
+    assert_eq!(RenderMode::Circles, parsed.mode);
+
+    let parsed = parse(&["--mode", "density:count", "--bin-size", "4"]).unwrap();
+    assert_eq!(RenderMode::Density(Weighting::Count), parsed.mode);
+    assert_eq!(4, parsed.bin_size);
+
+    let parsed = parse(&["--labels", "--overlay", "top-right", "--profile"]).unwrap();
+    assert!(parsed.labels);
+    assert!(parsed.profile);
+    assert_eq!(Some(Corner::TopRight), parsed.overlay);
+
+    assert_eq!(
+        Err(String::from("help")),
+        parse(&["-n", "20", "--help"]).map(|_| ())
+    );
+}
+
+#[test]
+fn test_parse_args_frames() {
+    let substeps = |args: &[&str]| {
+        let parsed = parse(args).unwrap();
+        parsed.substeps(&parsed.params)
+    };
+    assert_eq!(10, substeps(&["-i", "1000", "-s", "10"]));
+    assert_eq!(10, substeps(&["-i", "1000", "--frames", "100"]));
+    assert_eq!(4, substeps(&["-i", "1000", "--frames", "300"]));
+    // 2 seconds at 20ms a frame is 100 frames
+    let parsed = parse(&["-i", "1000", "--duration", "2", "--frame-delay", "20"]).unwrap();
+    assert_eq!(Frames::Total(100), parsed.frames);
+    assert_eq!(20, parsed.frame_delay);
+
+    // A target number of frames holds for every run of a sweep, however
+    // many steps each one has
+    let parsed = parse(&["--frames", "100", "--sweep", "iterations=1000,50"]).unwrap();
+    let runs = expand(parsed.params, &parsed.sweeps);
+    assert_eq!(10, parsed.substeps(&runs[0]));
+    assert_eq!(1, parsed.substeps(&runs[1]));
+
+    assert!(parse(&["-s", "0"]).is_err());
+    assert!(parse(&["--frames", "0"]).is_err());
+    assert!(parse(&["--duration", "-1"]).is_err());
+    assert!(parse(&["--frame-delay", "0"]).is_err());
+    assert!(parse(&["--frame-delay", "4000000000"]).is_err());
+    assert!(parse(&["-s", "2", "--frames", "10"]).is_err());
+    assert!(parse(&["--frames", "2", "--duration", "10"]).is_err());
+}
+
+#[test]
+fn test_parse_args_batch() {
+    let parsed = parse(&[
+        "--seed",
+        "4",
+        "--dt",
+        "0.5",
+        "--sweep",
+        "seed=1..3",
+        "-j",
+        "2",
+    ])
+    .unwrap();
+
+    assert_eq!(4, parsed.params.seed);
+    assert_eq!(0.5, parsed.params.dt);
+    assert_eq!(1, parsed.sweeps.len());
+    assert_eq!(2, parsed.jobs);
+
+    assert!(parse(&["--sweep", "mass=1,2"]).is_err());
+    assert!(parse(&["--jobs", "0"]).is_err());
+    assert!(parse(&["--tui", "--sweep", "seed=1,2"]).is_err());
+    assert!(parse(&["--sweep", "seed=1..100", "--sweep", "objects=1..100"]).is_ok());
+    assert!(parse(&["--sweep", "seed=1..100", "--sweep", "objects=1..101"]).is_err());
+    assert!(parse(&["--dt", "0"]).is_err());
+}
+
+#[test]
+fn test_parse_args_errors() {
+    assert!(parse(&["-n", "-5"]).is_err());
+    assert!(parse(&["-n", "lots"]).is_err());
+    assert!(parse(&["--iterations", "0"]).is_err());
+    assert!(parse(&["--output"]).is_err());
+    assert!(parse(&["--field", "magnet:1"]).is_err());
+    assert!(parse(&["--delta", "1"]).is_err());
+    assert_eq!(
+        Some(String::from("unknown option '--frobnicate'")),
+        parse(&["--frobnicate"]).err()
+    );
+    assert_eq!(
+        Some(String::from("missing value for --output")),
+        parse(&["--output"]).err()
+    );
+    assert!(parse(&["--field", "uniform:0,inf"]).is_err());
+    assert!(parse(&["--field", "point:1,2,NaN"]).is_err());
+    assert!(parse(&["--overlay", "middle"]).is_err());
+    assert!(parse(&["--mode", "sparkles"]).is_err());
+    assert!(parse(&["--pn", "0"]).is_err());
+    assert!(parse(&["--pn", "inf"]).is_err());
+    assert!(parse(&["--dt", "inf"]).is_err());
+    assert!(parse(&["--dt", "NaN"]).is_err());
+    assert!(parse(&["--collisions", "explode"]).is_err());
+    assert!(parse(&["--until", "bodies:few"]).is_err());
+    assert!(parse(&["--kernel", "gpu"]).is_err());
+    assert!(parse(&["--bin-size", "0"]).is_err());
+}
@@ -0,0 +1,331 @@
+use super::object::*;
+use super::simulation::*;
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// The most runs a batch can have, so a typo like seed=0..100000000 is an error
+// rather than an attempt to fill memory with every value in between
+pub const MAX_RUNS: usize = 10_000;
+
+// One parameter to vary across a batch, with every value to try
+#[derive(Debug, PartialEq)]
+pub struct Sweep {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+/// Parse a sweep given as `<name>=<values>`, where the values are either a
+/// comma separated list or an inclusive range of whole numbers like `1..20`.
+/// The names are `objects`, `iterations`, `dt` and `seed`.
+pub fn parse_sweep(s: &str) -> Result<Sweep, String> {
+    let index = s
+        .find('=')
+        .ok_or_else(|| format!("sweep '{}' should look like name=values", s))?;
+    let (name, values) = (&s[..index], &s[index + 1..]);
+
+    let values: Vec<String> = match values.find("..") {
+        Some(dots) => {
+            let bad_range = || format!("invalid range '{}' for {}", values, name);
+            let from: u64 = values[..dots].parse().map_err(|_| bad_range())?;
+            let to: u64 = values[dots + 2..].parse().map_err(|_| bad_range())?;
+            if to >= from && to - from >= MAX_RUNS as u64 {
+                return Err(format!(
+                    "range '{}' for {} has more than {} values",
+                    values, name, MAX_RUNS
+                ));
+            }
+            (from..=to).map(|v| v.to_string()).collect()
+        }
+        None => values.split(',').map(String::from).collect(),
+    };
+
+    if values.is_empty() {
+        return Err(format!("sweep over {} has no values", name));
+    }
+
+    // Check every value now rather than halfway through the batch
+    let mut params = Params {
+        num_objects: 0,
+        iterations: 0,
+        dt: 1.0,
+        seed: 0,
+    };
+    for value in &values {
+        apply(&mut params, name, value)?;
+    }
+
+    Ok(Sweep {
+        name: name.to_string(),
+        values,
+    })
+}
+
+pub fn apply(params: &mut Params, name: &str, value: &str) -> Result<(), String> {
+    let invalid = || format!("invalid value '{}' for {}", value, name);
+
+    match name {
+        "objects" => params.num_objects = value.parse().map_err(|_| invalid())?,
+        "iterations" => match value.parse() {
+            Ok(0) => return Err(String::from("iterations must be at least 1")),
+            Ok(iterations) => params.iterations = iterations,
+            Err(_) => return Err(invalid()),
+        },
+        "seed" => params.seed = value.parse().map_err(|_| invalid())?,
+        "dt" => match value.parse() {
+            Ok(dt) if f64::is_finite(dt) && dt > 0.0 => params.dt = dt,
+            _ => return Err(invalid()),
+        },
+        _ => return Err(format!("can't sweep over '{}'", name)),
+    }
+
+    Ok(())
+}
+
+// How many runs `expand` will make, or None if there are too many to count
+pub fn run_count(sweeps: &[Sweep]) -> Option<usize> {
+    sweeps
+        .iter()
+        .try_fold(1usize, |runs, sweep| runs.checked_mul(sweep.values.len()))
+}
+
+// Every combination of the swept values, applied on top of `base`
+pub fn expand(base: Params, sweeps: &[Sweep]) -> Vec<Params> {
+    let mut runs = vec![base];
+
+    for sweep in sweeps {
+        runs = runs
+            .iter()
+            .flat_map(|params| {
+                sweep.values.iter().map(move |value| {
+                    let mut params = *params;
+                    // Values were all checked when the sweep was parsed
+                    apply(&mut params, &sweep.name, value).unwrap();
+                    params
+                })
+            })
+            .collect();
+    }
+
+    runs
+}
+
+// How a single run of a batch ended up
+pub struct Summary {
+    pub params: Params,
+    pub steps: u32,
+    pub stopped: String,
+    pub final_bodies: usize,
+    pub merges: usize,
+    pub escapes: usize,
+    pub energy_drift: f64,
+    pub largest_mass: f64,
+}
+
+impl Summary {
+    pub fn of(sim: &Simulation, escape_radius: f64) -> Summary {
+        let objects = &sim.objects;
+
+        Summary {
+            params: sim.params,
+            steps: sim.step,
+            stopped: sim.stop_reason().unwrap_or_default(),
+            final_bodies: objects.len(),
+            merges: sim.merges,
+            escapes: escaped(objects, escape_radius).count(),
+            energy_drift: sim.energy_drift(),
+            largest_mass: objects.iter().map(|o| o.mass).fold(0.0, f64::max),
+        }
+    }
+}
+
+/// Run every set of parameters on `jobs` threads, handing out the next run
+/// to whichever thread is free. Summaries come back in the same order as
+/// `runs`; if any run fails, the first error is returned.
+pub fn run_batch<F>(runs: &[Params], jobs: usize, run_one: F) -> Result<Vec<Summary>, String>
+where
+    F: Fn(usize, Params) -> Result<Summary, String> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<Summary, String>>>> =
+        Mutex::new(runs.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(runs.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= runs.len() {
+                    break;
+                }
+
+                let result = run_one(i, runs[i]);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("every run has a result"))
+        .collect()
+}
+
+// Insert the run number before the extension, so out.webp becomes out-3.webp
+pub fn numbered(path: &str, run: usize) -> String {
+    match path.rfind('.') {
+        Some(dot) if !path[dot..].contains('/') => {
+            format!("{}-{}{}", &path[..dot], run, &path[dot..])
+        }
+        _ => format!("{}-{}", path, run),
+    }
+}
+
+// Quote a free-text CSV field, doubling any quotes inside it
+fn quoted(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+pub fn summary_csv(summaries: &[Summary]) -> String {
+    let mut csv = String::from(
+        "run,seed,objects,iterations,dt,steps,stopped,final_bodies,merges,escapes,energy_drift,largest_mass\n",
+    );
+
+    for (i, s) in summaries.iter().enumerate() {
+        // Writing to a String can't fail
+        writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{},{},{},{:e},{}",
+            i,
+            s.params.seed,
+            s.params.num_objects,
+            s.params.iterations,
+            s.params.dt,
+            s.steps,
+            quoted(&s.stopped),
+            s.final_bodies,
+            s.merges,
+            s.escapes,
+            s.energy_drift,
+            s.largest_mass
+        )
+        .unwrap();
+    }
+
+    csv
+}
+
+#[test]
+fn test_parse_sweep() {
+    assert_eq!(
+        Ok(Sweep {
+            name: String::from("seed"),
+            values: vec![String::from("3"), String::from("4"), String::from("5")],
+        }),
+        parse_sweep("seed=3..5")
+    );
+    assert_eq!(2, parse_sweep("dt=0.5,0.25").unwrap().values.len());
+
+    assert!(parse_sweep("seed").is_err());
+    assert!(parse_sweep("seed=1..x").is_err());
+    assert!(parse_sweep("seed=0..18446744073709551615").is_err());
+    assert_eq!(MAX_RUNS, parse_sweep("seed=1..10000").unwrap().values.len());
+    assert!(parse_sweep("seed=1..10001").is_err());
+    assert!(parse_sweep("seed=5..3").is_err());
+    assert!(parse_sweep("dt=0").is_err());
+    assert!(parse_sweep("dt=0.5,inf").is_err());
+    assert!(parse_sweep("iterations=0,100").is_err());
+    assert!(parse_sweep("iterations=0..2").is_err());
+    assert!(parse_sweep("objects=1,lots").is_err());
+    assert!(parse_sweep("colour=1,2").is_err());
+}
+
+#[test]
+fn test_expand() {
+    let base = Params {
+        num_objects: 10,
+        iterations: 100,
+        dt: 1.0,
+        seed: 0,
+    };
+    let sweeps = [
+        parse_sweep("seed=1..3").unwrap(),
+        parse_sweep("objects=20,40").unwrap(),
+    ];
+
+    let runs = expand(base, &sweeps);
+
+    assert_eq!(6, runs.len());
+    assert_eq!((1, 20), (runs[0].seed, runs[0].num_objects));
+    assert_eq!((3, 40), (runs[5].seed, runs[5].num_objects));
+    assert!(runs.iter().all(|r| r.iterations == 100));
+    assert_eq!(vec![base], expand(base, &[]));
+    assert_eq!(Some(6), run_count(&sweeps));
+    assert_eq!(Some(1), run_count(&[]));
+}
+
+#[test]
+fn test_run_batch_keeps_order() {
+    let runs: Vec<Params> = (0..20)
+        .map(|seed| Params {
+            num_objects: 5,
+            iterations: 10,
+            dt: 1.0,
+            seed,
+        })
+        .collect();
+
+    let summaries = run_batch(&runs, 4, |_, params| {
+        let physics = Physics::default();
+        let mut sim = Simulation::new(params, physics, &[], 1024);
+        while !sim.finished() {
+            sim.advance();
+        }
+        Ok(Summary::of(&sim, 1024.0))
+    })
+    .unwrap();
+
+    let seeds: Vec<u64> = summaries.iter().map(|s| s.params.seed).collect();
+    assert_eq!((0..20).collect::<Vec<u64>>(), seeds);
+    assert!(summaries.iter().all(|s| s.final_bodies + s.merges == 6));
+    assert!(summaries
+        .iter()
+        .all(|s| s.stopped == "ran all 10 iterations"));
+
+    assert!(run_batch(&runs, 4, |i, _| Err(format!("run {}", i))).is_err());
+}
+
+#[test]
+fn test_summary_csv() {
+    let summary = Summary {
+        params: Params {
+            num_objects: 5,
+            iterations: 10,
+            dt: 0.5,
+            seed: 7,
+        },
+        steps: 3,
+        stopped: String::from("energy drift 0.2 > 0.1, said \"stop\""),
+        final_bodies: 4,
+        merges: 1,
+        escapes: 0,
+        energy_drift: 0.2,
+        largest_mass: 2.5,
+    };
+
+    let csv = summary_csv(&[summary]);
+    let row = csv.lines().nth(1).unwrap();
+    assert_eq!(
+        "0,7,5,10,0.5,3,\"energy drift 0.2 > 0.1, said \"\"stop\"\"\",4,1,0,2e-1,2.5",
+        row
+    );
+}
+
+#[test]
+fn test_numbered() {
+    assert_eq!("out-3.webp", numbered("out.webp", 3));
+    assert_eq!("runs.d/out-3", numbered("runs.d/out", 3));
+}
@@ -0,0 +1,215 @@
+use super::object::*;
+use super::vec2::*;
+
+use std::fmt::Debug;
+
+// An external force acting on each body, evaluated alongside the mutual
+// gravity from `force_between`. `time` is the simulation time. Fields are
+// shared between the threads of a batch run, hence Send + Sync.
+pub trait ForceField: Send + Sync + Debug {
+    fn force_on(&self, obj: &Object, time: f64) -> Vec2;
+}
+
+// The same acceleration everywhere (e.g. "down"), so the force scales with mass.
+#[derive(Debug)]
+pub struct UniformGravity {
+    pub g: Vec2,
+}
+
+impl ForceField for UniformGravity {
+    fn force_on(&self, obj: &Object, _time: f64) -> Vec2 {
+        scale(&self.g, obj.mass)
+    }
+}
+
+// A fixed point mass that pulls on everything but never moves itself.
+#[derive(Debug)]
+pub struct PointMass {
+    pub position: Vec2,
+    pub mass: f64,
+}
+
+impl ForceField for PointMass {
+    fn force_on(&self, obj: &Object, _time: f64) -> Vec2 {
+        let uv = unit(&sub(&obj.position, &self.position));
+        let g = gravity(self.mass, obj.mass, distance(&self.position, &obj.position));
+
+        scale(&uv, g)
+    }
+}
+
+// A logarithmic potential, phi = v0^2 / 2 * ln(r^2 + core^2), which gives the
+// flat rotation curve of a galaxy's dark matter halo (circular speed -> v0).
+#[derive(Debug)]
+pub struct LogHalo {
+    pub centre: Vec2,
+    pub v0: f64,
+    pub core: f64,
+}
+
+impl ForceField for LogHalo {
+    fn force_on(&self, obj: &Object, _time: f64) -> Vec2 {
+        let r = distance(&self.centre, &obj.position);
+        let towards = sub(&obj.position, &self.centre);
+
+        scale(
+            &towards,
+            obj.mass * self.v0 * self.v0 / (r * r + self.core * self.core),
+        )
+    }
+}
+
+// Drag proportional to velocity (a viscous medium).
+#[derive(Debug)]
+pub struct LinearDrag {
+    pub k: f64,
+}
+
+impl ForceField for LinearDrag {
+    fn force_on(&self, obj: &Object, _time: f64) -> Vec2 {
+        scale(&obj.velocity, -self.k)
+    }
+}
+
+// Drag proportional to the square of the speed (air resistance).
+#[derive(Debug)]
+pub struct QuadraticDrag {
+    pub k: f64,
+}
+
+impl ForceField for QuadraticDrag {
+    fn force_on(&self, obj: &Object, _time: f64) -> Vec2 {
+        scale(&obj.velocity, -self.k * magnitude(&obj.velocity))
+    }
+}
+
+// A constant force on a single body between `start` and `end` (a burn).
+#[derive(Debug)]
+pub struct Thrust {
+    pub body: usize,
+    pub start: f64,
+    pub end: f64,
+    pub force: Vec2,
+}
+
+impl ForceField for Thrust {
+    fn force_on(&self, obj: &Object, time: f64) -> Vec2 {
+        if obj.id == self.body && time >= self.start && time < self.end {
+            self.force
+        } else {
+            VEC_ZERO
+        }
+    }
+}
+
+fn parse_numbers(s: &str) -> Option<Vec<f64>> {
+    s.split(',')
+        .map(|x| x.parse().ok().filter(|x: &f64| x.is_finite()))
+        .collect()
+}
+
+/// Parse a field from the command line, given as `<kind>:<params>`.
+///
+/// * `uniform:gx,gy`
+/// * `point:x,y,mass`
+/// * `halo:x,y,v0,core`
+/// * `drag:k` (linear) or `drag2:k` (quadratic)
+/// * `thrust:body,start,end,fx,fy`, where body is a name like `sun` or `body3`
+pub fn parse_field(s: &str) -> Option<Box<dyn ForceField>> {
+    let index = s.find(':')?;
+    let (kind, params) = (&s[..index], &s[index + 1..]);
+
+    if kind == "thrust" {
+        let index = params.find(',')?;
+        let body = parse_body_name(&params[..index])?;
+        return match parse_numbers(&params[index + 1..])?[..] {
+            [start, end, fx, fy] => Some(Box::new(Thrust {
+                body,
+                start,
+                end,
+                force: Vec2(fx, fy),
+            })),
+            _ => None,
+        };
+    }
+
+    let field: Box<dyn ForceField> = match (kind, &parse_numbers(params)?[..]) {
+        ("uniform", &[gx, gy]) => Box::new(UniformGravity { g: Vec2(gx, gy) }),
+        ("point", &[x, y, mass]) => Box::new(PointMass {
+            position: Vec2(x, y),
+            mass,
+        }),
+        ("halo", &[x, y, v0, core]) => Box::new(LogHalo {
+            centre: Vec2(x, y),
+            v0,
+            core,
+        }),
+        ("drag", &[k]) => Box::new(LinearDrag { k }),
+        ("drag2", &[k]) => Box::new(QuadraticDrag { k }),
+        _ => return None,
+    };
+
+    Some(field)
+}
+
+#[cfg(test)]
+fn moving(id: usize, velocity: Vec2) -> Object {
+    Object {
+        id,
+        position: Vec2(10.0, 0.0),
+        mass: 2.0,
+        velocity,
+        force: VEC_ZERO,
+    }
+}
+
+#[test]
+fn test_drag_opposes_velocity() {
+    let obj = moving(1, Vec2(3.0, 4.0));
+    let k = 0.5;
+
+    assert_eq!(Vec2(-1.5, -2.0), LinearDrag { k }.force_on(&obj, 0.0));
+    assert_eq!(Vec2(-7.5, -10.0), QuadraticDrag { k }.force_on(&obj, 0.0));
+}
+
+#[test]
+fn test_central_fields_pull_inwards() {
+    let obj = moving(1, VEC_ZERO);
+    let point = PointMass {
+        position: VEC_ZERO,
+        mass: 50.0,
+    };
+    let halo = LogHalo {
+        centre: VEC_ZERO,
+        v0: 1.0,
+        core: 0.0,
+    };
+
+    assert_eq!(Vec2(-1.0, 0.0), point.force_on(&obj, 0.0));
+    // v^2 / r is the centripetal acceleration of a circular orbit at speed v0
+    assert_eq!(Vec2(-0.2, 0.0), halo.force_on(&obj, 0.0));
+}
+
+#[test]
+fn test_thrust_only_during_burn() {
+    let thrust = parse_field("thrust:body3,10,20,1,0").unwrap();
+
+    assert_eq!(VEC_ZERO, thrust.force_on(&moving(3, VEC_ZERO), 5.0));
+    assert_eq!(Vec2(1.0, 0.0), thrust.force_on(&moving(3, VEC_ZERO), 10.0));
+    assert_eq!(VEC_ZERO, thrust.force_on(&moving(2, VEC_ZERO), 10.0));
+    assert_eq!(VEC_ZERO, thrust.force_on(&moving(3, VEC_ZERO), 20.0));
+}
+
+#[test]
+fn test_parse_field() {
+    assert!(parse_field("uniform:0,0.1").is_some());
+    assert!(parse_field("halo:512,512,1.5,20").is_some());
+    assert!(parse_field("thrust:sun,0,1,0,0").is_some());
+    assert!(parse_field("drag").is_none());
+    assert!(parse_field("drag:").is_none());
+    assert!(parse_field("point:1,2").is_none());
+    assert!(parse_field("magnet:1").is_none());
+    assert!(parse_field("uniform:0,inf").is_none());
+    assert!(parse_field("drag:NaN").is_none());
+    assert!(parse_field("thrust:sun,0,inf,0,0").is_none());
+}
@@ -0,0 +1,274 @@
+use super::object::*;
+#[cfg(test)]
+use super::scene::*;
+use super::vec2::*;
+
+use std::ops::{Add, AddAssign, Div, Mul, Sub};
+
+// How mutual gravity is summed up
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub enum Kernel {
+    // One body at a time through Vec2 and force_between; slow, but it's what
+    // the others are checked against
+    #[default]
+    Reference,
+    // Structure of arrays, in a shape the compiler can vectorise
+    F64,
+    // The same in single precision: twice as many lanes, a lot less precise
+    F32,
+}
+
+pub fn parse_kernel(s: &str) -> Option<Kernel> {
+    match s {
+        "reference" => Some(Kernel::Reference),
+        "f64" => Some(Kernel::F64),
+        "f32" => Some(Kernel::F32),
+        _ => None,
+    }
+}
+
+// The bits of f32 and f64 the kernel needs
+pub trait Real:
+    Copy
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + AddAssign
+{
+    const ZERO: Self;
+    const ONE: Self;
+
+    fn from_f64(x: f64) -> Self;
+    fn to_f64(self) -> f64;
+    fn sqrt(self) -> Self;
+}
+
+impl Real for f64 {
+    const ZERO: f64 = 0.0;
+    const ONE: f64 = 1.0;
+
+    #[inline]
+    fn from_f64(x: f64) -> f64 {
+        x
+    }
+
+    #[inline]
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    #[inline]
+    fn sqrt(self) -> f64 {
+        f64::sqrt(self)
+    }
+}
+
+impl Real for f32 {
+    const ZERO: f32 = 0.0;
+    const ONE: f32 = 1.0;
+
+    #[inline]
+    fn from_f64(x: f64) -> f32 {
+        x as f32
+    }
+
+    #[inline]
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    #[inline]
+    fn sqrt(self) -> f32 {
+        f32::sqrt(self)
+    }
+}
+
+// Bodies are summed this many at a time, with a separate total for each, so
+// the inner loop has no dependency from one body to the next. Enough for
+// AVX-512 in single precision.
+const LANES: usize = 16;
+
+// Positions and masses of the bodies, one array each. The arrays are padded
+// to a whole number of LANES with massless bodies, which pull on nothing.
+pub struct Bodies<T> {
+    pub len: usize,
+    pub x: Vec<T>,
+    pub y: Vec<T>,
+    pub mass: Vec<T>,
+}
+
+impl<T: Real> Bodies<T> {
+    pub fn new(objects: &[Object]) -> Bodies<T> {
+        let padded = objects.len().div_ceil(LANES) * LANES;
+        let column = |f: &dyn Fn(&Object) -> f64| {
+            let mut column: Vec<T> = objects.iter().map(|o| T::from_f64(f(o))).collect();
+            column.resize(padded, T::ZERO);
+            column
+        };
+
+        Bodies {
+            len: objects.len(),
+            x: column(&|o| o.position.0),
+            y: column(&|o| o.position.1),
+            mass: column(&|o| o.mass),
+        }
+    }
+}
+
+// 1 / r^3, or nothing for a body on top of another (or itself)
+#[inline(always)]
+fn pull<T: Real>(r2: T) -> T {
+    let inverse = T::ONE / (r2 * r2.sqrt());
+    if r2 > T::ZERO {
+        inverse
+    } else {
+        T::ZERO
+    }
+}
+
+/// The gravitational force on every body from all the others, by direct
+/// summation. Gives the same answer as adding up `force_between` for every
+/// pair, to within rounding.
+pub fn direct_sum<T: Real>(bodies: &Bodies<T>) -> Vec<Vec2> {
+    let columns = bodies
+        .x
+        .chunks_exact(LANES)
+        .zip(bodies.y.chunks_exact(LANES))
+        .zip(bodies.mass.chunks_exact(LANES));
+
+    (0..bodies.len)
+        .map(|i| {
+            let (xi, yi) = (bodies.x[i], bodies.y[i]);
+            let mut fx = [T::ZERO; LANES];
+            let mut fy = [T::ZERO; LANES];
+
+            for ((xs, ys), ms) in columns.clone() {
+                for k in 0..LANES {
+                    let dx = xs[k] - xi;
+                    let dy = ys[k] - yi;
+                    let f = ms[k] * pull(dx * dx + dy * dy);
+                    fx[k] += f * dx;
+                    fy[k] += f * dy;
+                }
+            }
+
+            let sum = |lanes: [T; LANES]| lanes.iter().fold(T::ZERO, |acc, &f| acc + f);
+            let m = bodies.mass[i];
+            Vec2((sum(fx) * m).to_f64(), (sum(fy) * m).to_f64())
+        })
+        .collect()
+}
+
+// Mutual gravity on every body, however the physics says to sum it
+pub fn gravity_on_all(a: &[Object], kernel: Kernel) -> Vec<Vec2> {
+    match kernel {
+        Kernel::Reference => a
+            .iter()
+            .map(|o| {
+                a.iter()
+                    .fold(VEC_ZERO, |acc, x| add(&acc, &force_between(x, o)))
+            })
+            .collect(),
+        Kernel::F64 => direct_sum(&Bodies::<f64>::new(a)),
+        Kernel::F32 => direct_sum(&Bodies::<f32>::new(a)),
+    }
+}
+
+// The largest difference between each kernel's forces and the reference,
+// relative to the largest force
+#[cfg(test)]
+fn kernel_error(objects: &[Object], kernel: Kernel) -> f64 {
+    let reference = gravity_on_all(objects, Kernel::Reference);
+    let forces = gravity_on_all(objects, kernel);
+    let largest = reference.iter().map(magnitude).fold(0.0, f64::max);
+
+    reference
+        .iter()
+        .zip(&forces)
+        .map(|(r, f)| distance(r, f) / largest)
+        .fold(0.0, f64::max)
+}
+
+#[test]
+fn test_parse_kernel() {
+    assert_eq!(Some(Kernel::Reference), parse_kernel("reference"));
+    assert_eq!(Some(Kernel::F32), parse_kernel("f32"));
+    assert_eq!(None, parse_kernel("f16"));
+}
+
+#[test]
+fn test_kernels_match_reference() {
+    // Including sizes that don't fill the last set of lanes
+    for (num_objects, seed) in [(1, 1), (15, 2), (16, 3), (100, 4), (333, 5)] {
+        let objects = Scene {
+            space_size: 1024,
+            num_objects,
+            seed,
+        }
+        .create();
+
+        let f64_error = kernel_error(&objects, Kernel::F64);
+        let f32_error = kernel_error(&objects, Kernel::F32);
+
+        assert!(f64_error < 1e-12, "f64 kernel off by {}", f64_error);
+        assert!(f32_error < 1e-4, "f32 kernel off by {}", f32_error);
+    }
+}
+
+#[test]
+fn test_kernel_ignores_coincident_bodies() {
+    let body = |id, x| Object {
+        id,
+        position: Vec2(x, 0.0),
+        mass: 2.0,
+        velocity: VEC_ZERO,
+        force: VEC_ZERO,
+    };
+    let objects = [body(0, 0.0), body(1, 0.0), body(2, 2.0)];
+
+    // Each of the first two feels only the third, 2 away
+    let forces = gravity_on_all(&objects, Kernel::F32);
+    assert_eq!(Vec2(1.0, 0.0), forces[0]);
+    assert_eq!(Vec2(1.0, 0.0), forces[1]);
+    assert_eq!(Vec2(-2.0, 0.0), forces[2]);
+}
+
+#[test]
+fn test_simulation_with_kernel_follows_reference() {
+    let objects = Scene {
+        space_size: 1024,
+        num_objects: 50,
+        seed: 11,
+    }
+    .create();
+
+    let run = |kernel| {
+        let physics = Physics {
+            kernel,
+            ..Physics::default()
+        };
+        let mut next_id = next_free_id(&objects);
+        (0..100).fold(objects.clone(), |objects, step| {
+            update_all(
+                &objects,
+                &physics,
+                step as f64,
+                1.0,
+                &mut next_id,
+                &mut Vec::new(),
+                None,
+            )
+        })
+    };
+
+    let reference = run(Kernel::Reference);
+    let fast = run(Kernel::F64);
+
+    assert_eq!(reference.len(), fast.len());
+    for (r, f) in reference.iter().zip(&fast) {
+        assert_eq!(r.id, f.id);
+        assert!(distance(&r.position, &f.position) < 1e-6);
+    }
+}
@@ -0,0 +1,16 @@
+// Everything apart from main lives here, so that the benchmarks can use it too
+
+pub mod args;
+pub mod batch;
+pub mod field;
+pub mod kernel;
+pub mod manifest;
+pub mod object;
+pub mod profile;
+pub mod render;
+pub mod scene;
+pub mod simulation;
+pub mod stop;
+pub mod svg;
+pub mod tui;
+pub mod vec2;
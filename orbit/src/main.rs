@@ -5,81 +5,243 @@
 // - Replace vomit inducing pairs gubbins near the bottom
 // - Improve type safety of Vec2
 
-mod object;
-mod render;
-mod scene;
-mod vec2;
-
+use std::convert::TryFrom;
 use std::env;
+use std::time::Instant;
 use text_colorizer::*;
 use webp_animation::Encoder;
 
-use object::*;
-use render::*;
-use scene::*;
+use orbit::args::*;
+use orbit::batch::*;
+use orbit::manifest::*;
+use orbit::render::*;
+use orbit::simulation::*;
+use orbit::svg::*;
+use orbit::tui::*;
 
 const IMAGE_SIZE: u32 = 1024;
 
 fn main() {
-    let args = parse_args();
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    let scene = Scene {
-        num_objects: args.num_objects as u32,
-        space_size: IMAGE_SIZE,
+    let args = match expand_manifests(&args).and_then(|args| parse_args(&args)) {
+        Ok(Command::Run(args)) => args,
+        Ok(Command::Help) => {
+            print_usage();
+            return;
+        }
+        Err(e) => {
+            print_usage();
+            eprintln!();
+            eprintln!("{} {}.", "Error:".red().bold(), e);
+            std::process::exit(2);
+        }
     };
-    let mut objects = scene.create();
+
+    if let Err(e) = run(&args) {
+        eprintln!("{} {}", "Error:".red().bold(), e);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: &Arguments) -> Result<(), String> {
+    if args.tui {
+        let sim = Simulation::new(args.params, args.physics(), &args.until, IMAGE_SIZE);
+        let sim = run_tui(sim, IMAGE_SIZE as f64)?;
+        report_stop(args, &sim);
+        report_profile(args, &sim);
+        return Ok(());
+    }
+
+    if args.sweeps.is_empty() {
+        let output = args.output.as_deref().unwrap_or("orbit.webp");
+        let sim = simulate(args, args.params, Some(output), args.svg.as_deref())?;
+        report_stop(args, &sim);
+        report_profile(args, &sim);
+        return Ok(());
+    }
+
+    let runs = expand(args.params, &args.sweeps);
+    let summaries = run_batch(&runs, args.jobs, |i, params| {
+        let output = args.output.as_ref().map(|path| numbered(path, i));
+        let svg = args.svg.as_ref().map(|path| numbered(path, i));
+        let sim = simulate(args, params, output.as_deref(), svg.as_deref())?;
+        report_profile(args, &sim);
+
+        Ok(Summary::of(&sim, IMAGE_SIZE as f64))
+    })?;
+
+    let csv = summary_csv(&summaries);
+    match &args.summary {
+        Some(path) => std::fs::write(path, csv)
+            .map_err(|e| format!("failed to write to file '{}': {}", path, e)),
+        None => {
+            print!("{}", csv);
+            Ok(())
+        }
+    }
+}
+
+// When a frame is shown, in milliseconds from the start of the animation
+fn timestamp(frame: u32, frame_delay: u32) -> Result<i32, String> {
+    frame
+        .checked_mul(frame_delay)
+        .and_then(|ms| i32::try_from(ms).ok())
+        .ok_or_else(|| String::from("the animation is too long; use fewer frames"))
+}
+
+// Run one simulation, writing the animation and trajectory plot only if
+// there's somewhere to put them.
+fn simulate<'a>(
+    args: &'a Arguments,
+    params: Params,
+    output: Option<&str>,
+    svg: Option<&str>,
+) -> Result<Simulation<'a>, String> {
+    let started = Instant::now();
+    let mut sim = Simulation::new(params, args.physics(), &args.until, IMAGE_SIZE);
+    let mut bodies = vec![(0, sim.objects.len())];
+    let camera = Camera::fit(IMAGE_SIZE as f64, IMAGE_SIZE as usize);
+    let mut trajectories = svg.map(|_| Trajectories::new(&sim.objects));
 
     let dimensions = (IMAGE_SIZE, IMAGE_SIZE);
-    const BUFFER_SIZE: usize = (IMAGE_SIZE as usize) * (IMAGE_SIZE as usize);
-    let mut encoder = Encoder::new(dimensions).unwrap();
+    let mut encoder = match output {
+        Some(_) => Some(
+            Encoder::new(dimensions).map_err(|e| format!("failed to create encoder: {:?}", e))?,
+        ),
+        None => None,
+    };
+
+    let substeps = args.substeps(&params);
+    let mut frame_count = 0;
+
+    // There's always at least one frame, even if the run stops before its
+    // first step, as an animation can't be empty
+    loop {
+        // The last frame may have fewer steps if they don't divide evenly,
+        // or if the run stops early
+        for _ in 0..substeps {
+            if sim.finished() {
+                break;
+            }
+            sim.advance();
+
+            if bodies.last().map(|&(_, n)| n) != Some(sim.objects.len()) {
+                bodies.push((sim.step, sim.objects.len()));
+            }
+
+            if let Some(trajectories) = &mut trajectories {
+                trajectories.collided(&sim.collisions);
+            }
+        }
+
+        if let Some(trajectories) = &mut trajectories {
+            trajectories.record(&sim.objects);
+        }
 
-    for i in 0..args.iterations {
-        let mut frame = Frame {
-            values: [0, 0, 0, 255].repeat(BUFFER_SIZE),
-            size: IMAGE_SIZE as usize,
-        };
+        if let Some(encoder) = &mut encoder {
+            let start = Instant::now();
+            let frame = draw_frame(&sim, args, camera);
+            sim.profile.record("render", start);
 
-        objects = update_all(&objects);
+            let start = Instant::now();
+            encoder
+                .add_frame(frame.values(), timestamp(frame_count, args.frame_delay)?)
+                .map_err(|e| format!("failed to encode frame {}: {:?}", frame_count, e))?;
+            sim.profile.record("encode", start);
+        }
+        frame_count += 1;
 
-        for &object in &objects {
-            frame.render(&object);
+        if sim.finished() {
+            break;
         }
+    }
 
-        encoder.add_frame(&frame.values(), i).unwrap();
+    if let (Some(path), Some(encoder)) = (output, encoder) {
+        let start = Instant::now();
+        let webp_data = encoder
+            .finalize(timestamp(frame_count, args.frame_delay)?)
+            .map_err(|e| format!("failed to finish animation: {:?}", e))?;
+        sim.profile.record("encode", start);
+        std::fs::write(path, webp_data)
+            .map_err(|e| format!("failed to write to file '{}': {}", path, e))?;
     }
 
-    let webp_data = encoder.finalize(args.iterations + 1).unwrap();
-    std::fs::write(args.output, webp_data).unwrap();
-}
+    if let (Some(path), Some(trajectories)) = (svg, &trajectories) {
+        std::fs::write(path, trajectories.to_svg(&camera))
+            .map_err(|e| format!("failed to write to file '{}': {}", path, e))?;
+    }
 
-#[derive(Debug)]
-struct Arguments {
-    num_objects: i32,
-    iterations: i32,
-    output: String,
-}
+    if let Some(path) = manifest_path(output, svg) {
+        if bodies.last().map(|&(step, _)| step) != Some(sim.step) {
+            bodies.push((sim.step, sim.objects.len()));
+        }
 
-fn print_usage() {
-    eprintln!("{} - simulate some bodies under gravity", "orbit".green());
-    eprintln!("Usage: orbit <num_objects> <delta>  <output>");
+        let run_args = reproducible_args(args, &params, output, svg);
+        let json = manifest(args, &run_args, &sim, &bodies, started.elapsed());
+        std::fs::write(&path, json)
+            .map_err(|e| format!("failed to write to file '{}': {}", path, e))?;
+    }
+
+    Ok(sim)
 }
 
-fn parse_args() -> Arguments {
-    let args: Vec<String> = env::args().skip(1).collect();
+// Only worth saying if the run might have stopped early
+fn report_stop(args: &Arguments, sim: &Simulation) {
+    if let (false, Some(reason)) = (args.until.is_empty(), sim.stop_reason()) {
+        eprintln!("Stopped after {} steps: {}.", sim.step, reason);
+    }
+}
 
-    if args.len() != 3 {
-        print_usage();
+fn report_profile(args: &Arguments, sim: &Simulation) {
+    if args.profile {
         eprintln!(
-            "{} wrong number of arguments:@ expected 3, got {}.",
-            "Error:".red().bold(),
-            args.len()
+            "Profile of {} steps with seed {}:",
+            sim.step, sim.params.seed
         );
-        std::process::exit(1);
+        eprint!("{}", sim.profile.report());
+    }
+}
+
+fn draw_frame(sim: &Simulation, args: &Arguments, camera: Camera) -> Frame {
+    let mut frame = Frame::new(camera);
+
+    frame.render_all(&sim.objects, args.mode, args.bin_size);
+
+    if args.labels {
+        for object in &sim.objects {
+            frame.render_label(object);
+        }
     }
 
-    Arguments {
-        num_objects: args[0].parse().unwrap(),
-        iterations: args[1].parse().unwrap(),
-        output: args[2].clone(),
+    if let Some(corner) = args.overlay {
+        let lines = [
+            format!("step {}", sim.step),
+            format!("time {:.1}", sim.time()),
+            format!("bodies {}", sim.objects.len()),
+            format!("drift {:+.3e}", sim.energy_drift()),
+        ];
+        frame.render_overlay(corner, &lines);
     }
+
+    frame
+}
+
+#[test]
+fn test_simulate_stopped_at_start() {
+    // Already down to no more than 1000 bodies, so it stops at step 0
+    let args = Arguments {
+        until: vec![orbit::stop::Until::Bodies(1000)],
+        ..Arguments::default()
+    };
+    let path = std::env::temp_dir().join(format!("orbit-stopped-{}.webp", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    let sim = simulate(&args, args.params, Some(path), None).unwrap();
+    let written = std::fs::read(path);
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(manifest_path(Some(path), None).unwrap()).unwrap();
+
+    assert_eq!(0, sim.step);
+    assert!(written.unwrap().starts_with(b"RIFF"));
 }
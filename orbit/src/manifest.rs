@@ -0,0 +1,283 @@
+use super::args::*;
+use super::simulation::*;
+
+use serde_json::{json, Value};
+use std::time::Duration;
+
+// Options left out of a reproducible command line, either because they only
+// make sense for a whole batch or because they're added back for each run.
+// A target number of frames or length is replaced by the substeps it worked
+// out to, which depend on the run's iterations. They all take a value.
+const REPLACED_OPTIONS: [&str; 17] = [
+    "--sweep",
+    "-j",
+    "--jobs",
+    "--summary",
+    "-n",
+    "--objects",
+    "-i",
+    "--iterations",
+    "--dt",
+    "--seed",
+    "-o",
+    "--output",
+    "--svg",
+    "-s",
+    "--substeps",
+    "--frames",
+    "--duration",
+];
+
+// Where the manifest for a run goes: next to its animation, or its SVG if it
+// doesn't have one.
+pub fn manifest_path(output: Option<&str>, svg: Option<&str>) -> Option<String> {
+    output.or(svg).map(|path| format!("{}.json", path))
+}
+
+/// The command line that repeats a single run exactly: what was given, less
+/// any batch options, with this run's parameters and output files spelled
+/// out at the end.
+pub fn reproducible_args(
+    args: &Arguments,
+    params: &Params,
+    output: Option<&str>,
+    svg: Option<&str>,
+) -> Vec<String> {
+    let mut reproducible = Vec::new();
+    let mut iter = args.command_line.iter();
+
+    while let Some(arg) = iter.next() {
+        let name = arg.split('=').next().unwrap_or(arg);
+        if !REPLACED_OPTIONS.contains(&name) {
+            reproducible.push(arg.clone());
+        } else if !arg.contains('=') {
+            // Skip the value too
+            iter.next();
+        }
+    }
+
+    let mut set = |name: &str, value: String| {
+        reproducible.push(name.to_string());
+        reproducible.push(value);
+    };
+    set("--objects", params.num_objects.to_string());
+    set("--iterations", params.iterations.to_string());
+    // Display for f64 gives back exactly the same number when parsed
+    set("--dt", params.dt.to_string());
+    set("--seed", params.seed.to_string());
+    set("--substeps", args.substeps(params).to_string());
+    if let Some(path) = output {
+        set("--output", path.to_string());
+    }
+    if let Some(path) = svg {
+        set("--svg", path.to_string());
+    }
+
+    reproducible
+}
+
+/// Everything about how a run was produced, as pretty printed JSON.
+/// `bodies` is the number of bodies at each step where it changed.
+pub fn manifest(
+    args: &Arguments,
+    run_args: &[String],
+    sim: &Simulation,
+    bodies: &[(u32, usize)],
+    wall_clock: Duration,
+) -> String {
+    let stages: serde_json::Map<String, Value> = sim
+        .profile
+        .stages()
+        .map(|(name, time)| (name.to_string(), json!(time.as_secs_f64())))
+        .collect();
+
+    let manifest = json!({
+        "version": format!("orbit {}", env!("CARGO_PKG_VERSION")),
+        "args": run_args,
+        "settings": {
+            "objects": sim.params.num_objects,
+            "iterations": sim.params.iterations,
+            "dt": sim.params.dt,
+            "seed": sim.params.seed,
+            "substeps": args.substeps(&sim.params),
+            "frame_delay_ms": args.frame_delay,
+            "mode": format!("{:?}", args.mode),
+            "bin_size": args.bin_size,
+            "fields": args.fields.iter().map(|f| format!("{:?}", f)).collect::<Vec<_>>(),
+            "speed_of_light": args.speed_of_light,
+            "collisions": format!("{:?}", args.collisions),
+            "until": args.until.iter().map(|u| format!("{:?}", u)).collect::<Vec<_>>(),
+        },
+        "integrator": {
+            "method": "semi-implicit Euler",
+            "gravity": "direct summation",
+            "kernel": format!("{:?}", args.kernel),
+        },
+        "result": {
+            "steps": sim.step,
+            "stopped": sim.stop_reason(),
+            "time": sim.time(),
+            "energy_drift": sim.energy_drift(),
+        },
+        "bodies": bodies.iter().map(|&(step, count)| json!([step, count])).collect::<Vec<_>>(),
+        "timing": {
+            "wall_clock_secs": wall_clock.as_secs_f64(),
+            "stage_secs": stages,
+        },
+    });
+
+    // A Value can always be written out
+    serde_json::to_string_pretty(&manifest).unwrap() + "\n"
+}
+
+// The arguments recorded in a manifest
+pub fn load_manifest(path: &str) -> Result<Vec<String>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read manifest '{}': {}", path, e))?;
+    let manifest: Value = serde_json::from_str(&text)
+        .map_err(|e| format!("manifest '{}' isn't valid JSON: {}", path, e))?;
+
+    manifest["args"]
+        .as_array()
+        .and_then(|args| args.iter().map(|a| a.as_str().map(String::from)).collect())
+        .ok_or_else(|| format!("manifest '{}' has no list of args", path))
+}
+
+/// Replace any `--from-manifest FILE` with the arguments recorded in that
+/// manifest, so that anything after it can still override them.
+pub fn expand_manifests(args: &[String]) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::new();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        let path = match arg.strip_prefix("--from-manifest") {
+            Some("") => iter
+                .next()
+                .ok_or_else(|| String::from("missing value for --from-manifest"))?,
+            Some(inline) if inline.starts_with('=') => &inline[1..],
+            _ => {
+                expanded.push(arg.clone());
+                continue;
+            }
+        };
+        expanded.extend(load_manifest(path)?);
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_reproducible_args() {
+    let command_line = strings(&[
+        "--sweep",
+        "seed=1..4",
+        "-n",
+        "20",
+        "--jobs=2",
+        "--mode",
+        "velocity",
+        "-o",
+        "out.webp",
+    ]);
+    let args = match parse_args(&command_line).unwrap() {
+        Command::Run(args) => args,
+        Command::Help => panic!("not asking for help"),
+    };
+    let params = Params {
+        seed: 3,
+        ..args.params
+    };
+
+    let run_args = reproducible_args(&args, &params, Some("out-2.webp"), None);
+
+    assert_eq!(
+        strings(&[
+            "--mode",
+            "velocity",
+            "--objects",
+            "20",
+            "--iterations",
+            "500",
+            "--dt",
+            "1",
+            "--seed",
+            "3",
+            "--substeps",
+            "1",
+            "--output",
+            "out-2.webp"
+        ]),
+        run_args
+    );
+
+    // Which parse back to exactly the same run
+    let reparsed = match parse_args(&run_args).unwrap() {
+        Command::Run(args) => args,
+        Command::Help => panic!("not asking for help"),
+    };
+    assert_eq!(params, reparsed.params);
+    assert!(reparsed.sweeps.is_empty());
+    assert_eq!(Some(String::from("out-2.webp")), reparsed.output);
+
+    // A target number of frames becomes however many substeps it meant for
+    // this run
+    let command_line = strings(&["--frames=10", "--sweep", "iterations=100,200"]);
+    let args = match parse_args(&command_line).unwrap() {
+        Command::Run(args) => args,
+        Command::Help => panic!("not asking for help"),
+    };
+    let params = Params {
+        iterations: 200,
+        ..args.params
+    };
+    let run_args = reproducible_args(&args, &params, None, None);
+    assert!(!run_args.iter().any(|arg| arg.starts_with("--frames")));
+    assert!(run_args.ends_with(&strings(&["--substeps", "20"])));
+}
+
+#[test]
+fn test_manifest_round_trip() {
+    let args = match parse_args(&strings(&["-n", "5", "-i", "20", "--dt", "0.1"])).unwrap() {
+        Command::Run(args) => args,
+        Command::Help => panic!("not asking for help"),
+    };
+    let mut sim = Simulation::new(args.params, args.physics(), &args.until, 1024);
+    while !sim.finished() {
+        sim.advance();
+    }
+    let run_args = reproducible_args(&args, &args.params, Some("a.webp"), None);
+
+    let json = manifest(
+        &args,
+        &run_args,
+        &sim,
+        &[(0, 6), (20, 6)],
+        Duration::from_millis(1500),
+    );
+    let value: Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(json!(5), value["settings"]["objects"]);
+    assert_eq!(json!(0.1), value["settings"]["dt"]);
+    assert_eq!(json!("ran all 20 iterations"), value["result"]["stopped"]);
+    assert_eq!(json!([[0, 6], [20, 6]]), value["bodies"]);
+    assert_eq!(json!(1.5), value["timing"]["wall_clock_secs"]);
+    assert!(value["timing"]["stage_secs"]["forces"].is_number());
+
+    let path = std::env::temp_dir().join(format!("orbit-manifest-{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    std::fs::write(path, json).unwrap();
+
+    let expanded = expand_manifests(&strings(&["--from-manifest", path, "-o", "b.webp"]));
+    std::fs::remove_file(path).unwrap();
+
+    let mut expected = run_args.clone();
+    expected.extend(strings(&["-o", "b.webp"]));
+    assert_eq!(Ok(expected), expanded);
+
+    assert!(expand_manifests(&strings(&["--from-manifest"])).is_err());
+    assert!(expand_manifests(&strings(&["--from-manifest=/no/such/file.json"])).is_err());
+}
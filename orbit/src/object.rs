@@ -1,13 +1,81 @@
+use super::field::*;
+use super::kernel::*;
+use super::profile::*;
 use super::vec2::*;
 
+use std::time::Instant;
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub struct Object {
+    pub id: usize,
     pub position: Vec2,
     pub mass: f64,
     pub velocity: Vec2,
     pub force: Vec2,
 }
 
+// Bodies are referred to by id; the scene always creates the sun first, so
+// it's id 0 and can be called "sun". Everything else is "body<id>" (or just
+// the bare number).
+pub fn body_name(id: usize) -> String {
+    if id == 0 {
+        String::from("sun")
+    } else {
+        format!("body{}", id)
+    }
+}
+
+pub fn parse_body_name(s: &str) -> Option<usize> {
+    match s {
+        "sun" => Some(0),
+        _ => s.strip_prefix("body").unwrap_or(s).parse().ok(),
+    }
+}
+
+// Bodies closer than this have collided
+pub const COLLISION_DISTANCE: f64 = 3.0;
+
+// Fragments lighter than this would just be dust, so bodies that small merge
+// however hard they hit.
+pub const MIN_FRAGMENT_MASS: f64 = 0.01;
+
+// What happens when two bodies collide
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub enum Collisions {
+    // They stick together
+    #[default]
+    Merge,
+    // They bounce off each other without losing any energy
+    Bounce,
+    // They stick together when they meet slowly, but an impact faster than
+    // their mutual escape velocity shatters them into this many pieces
+    Fragment(usize),
+}
+
+// merge, bounce, fragment or fragment:<pieces>
+pub fn parse_collisions(s: &str) -> Option<Collisions> {
+    match s {
+        "merge" => Some(Collisions::Merge),
+        "bounce" => Some(Collisions::Bounce),
+        "fragment" => Some(Collisions::Fragment(4)),
+        _ => match s.strip_prefix("fragment:")?.parse() {
+            Ok(pieces) if pieces >= 2 => Some(Collisions::Fragment(pieces)),
+            _ => None,
+        },
+    }
+}
+
+// Everything that decides how bodies interact, apart from the bodies themselves
+#[derive(Default)]
+pub struct Physics<'a> {
+    pub fields: &'a [Box<dyn ForceField>],
+    // When set, gravity includes the first post-Newtonian correction for
+    // light travelling at this speed (in simulation units).
+    pub speed_of_light: Option<f64>,
+    pub collisions: Collisions,
+    pub kernel: Kernel,
+}
+
 pub fn gravity(m1: f64, m2: f64, r: f64) -> f64 {
     if r == 0.0 {
         0.0
@@ -23,14 +91,100 @@ pub fn force_between(a: &Object, b: &Object) -> Vec2 {
     scale(&uv, g)
 }
 
-pub fn accumulate_forces(a: &Object, b: &[Object]) -> Object {
-    let f = b
-        .iter()
-        .fold(VEC_ZERO, |acc, x| add(&acc, &force_between(x, a)));
+// The 1PN correction to the force `a` exerts on `b`, in the limit where `b` is
+// a test particle moving around `a` (so it's exact for a planet around a sun,
+// and doesn't quite obey Newton's third law for comparable masses):
+//
+//   a = m / (c^2 r^2) * ((4m / r - v^2) n + 4 (n.v) v)
+//
+// where n is the unit vector from `a` to `b` and v the relative velocity. This
+// is what makes Mercury's perihelion precess.
+pub fn post_newtonian_between(a: &Object, b: &Object, c: f64) -> Vec2 {
+    let r = distance(&a.position, &b.position);
+    if r == 0.0 {
+        return VEC_ZERO;
+    }
+
+    let n = unit(&sub(&a.position, &b.position));
+    let v = sub(&a.velocity, &b.velocity);
+    let v2 = dot(&v, &v);
+    let radial = scale(&n, 4.0 * a.mass / r - v2);
+    let along = scale(&v, 4.0 * dot(&n, &v));
+
+    scale(&add(&radial, &along), b.mass * a.mass / (c * c * r * r))
+}
+
+// Kinetic energy plus the potential energy of mutual gravity (external fields
+// aren't included, so work done by drag or thrust shows up as drift).
+pub fn total_energy(a: &[Object]) -> f64 {
+    let mut energy = 0.0;
+
+    for (i, x) in a.iter().enumerate() {
+        energy += 0.5 * x.mass * magnitude(&x.velocity).powi(2);
+
+        for y in &a[i + 1..] {
+            let r = distance(&x.position, &y.position);
+            if r > 0.0 {
+                energy -= x.mass * y.mass / r;
+            }
+        }
+    }
+
+    energy
+}
+
+// Total mass, and the position and velocity of the centre of mass
+pub fn centre_of_mass(a: &[Object]) -> (f64, Vec2, Vec2) {
+    let mass: f64 = a.iter().map(|o| o.mass).sum();
+    let weighted = |f: &dyn Fn(&Object) -> Vec2| {
+        a.iter()
+            .fold(VEC_ZERO, |acc, o| add(&acc, &scale(&f(o), o.mass / mass)))
+    };
+
+    (mass, weighted(&|o| o.position), weighted(&|o| o.velocity))
+}
+
+// A body has escaped once it's further than `radius` from the centre of mass
+// and moving fast enough that the rest of the system can't pull it back.
+pub fn has_escaped(obj: &Object, a: &[Object], radius: f64) -> bool {
+    escaped_from(obj, centre_of_mass(a), radius)
+}
+
+// Every body in `a` that has escaped, only working out the centre of mass once
+pub fn escaped(a: &[Object], radius: f64) -> impl Iterator<Item = &Object> {
+    let centre = centre_of_mass(a);
+    a.iter().filter(move |o| escaped_from(o, centre, radius))
+}
+
+fn escaped_from(obj: &Object, (mass, position, velocity): (f64, Vec2, Vec2), radius: f64) -> bool {
+    let r = distance(&obj.position, &position);
+    let v = magnitude(&sub(&velocity, &obj.velocity));
+
+    r > radius && 0.5 * v * v > (mass - obj.mass) / r
+}
+
+// Everything else acting on `a`, on top of the Newtonian `gravity` from `b`
+pub fn accumulate_forces(
+    a: &Object,
+    gravity: &Vec2,
+    b: &[Object],
+    physics: &Physics,
+    time: f64,
+) -> Object {
+    let f = *gravity;
+    let f = match physics.speed_of_light {
+        Some(c) => b
+            .iter()
+            .filter(|x| x.id != a.id)
+            .fold(f, |acc, x| add(&acc, &post_newtonian_between(x, a, c))),
+        None => f,
+    };
+    let f = add(&f, &external_force(a, physics.fields, time));
 
     //println!("Force {:?}", f);
 
     Object {
+        id: a.id,
         position: Vec2(a.position.0, a.position.1),
         mass: a.mass,
         velocity: Vec2(a.velocity.0, a.velocity.1),
@@ -38,14 +192,26 @@ pub fn accumulate_forces(a: &Object, b: &[Object]) -> Object {
     }
 }
 
-pub fn calculate_forces_on_all(a: &[Object]) -> Vec<Object> {
-    a.iter().map(|o| accumulate_forces(o, a)).collect()
+// The sum of all the external fields acting on a single body
+pub fn external_force(a: &Object, fields: &[Box<dyn ForceField>], time: f64) -> Vec2 {
+    fields
+        .iter()
+        .fold(VEC_ZERO, |acc, field| add(&acc, &field.force_on(a, time)))
+}
+
+pub fn calculate_forces_on_all(a: &[Object], physics: &Physics, time: f64) -> Vec<Object> {
+    gravity_on_all(a, physics.kernel)
+        .iter()
+        .zip(a)
+        .map(|(g, o)| accumulate_forces(o, g, a, physics, time))
+        .collect()
 }
 
-pub fn accelerate(o: &Object) -> Object {
-    let av = add(&o.velocity, &scale(&o.force, 1.0 / o.mass));
+pub fn accelerate(o: &Object, dt: f64) -> Object {
+    let av = add(&o.velocity, &scale(&o.force, dt / o.mass));
 
     Object {
+        id: o.id,
         position: Vec2(o.position.0, o.position.1),
         mass: o.mass,
         force: VEC_ZERO,
@@ -53,44 +219,48 @@ pub fn accelerate(o: &Object) -> Object {
     }
 }
 
-pub fn accelerate_all(objs: &[Object]) -> Vec<Object> {
-    objs.iter().map(accelerate).collect()
+pub fn accelerate_all(objs: &[Object], dt: f64) -> Vec<Object> {
+    objs.iter().map(|o| accelerate(o, dt)).collect()
 }
 
-pub fn reposition(a: &Object) -> Object {
+pub fn reposition(a: &Object, dt: f64) -> Object {
     Object {
-        position: add(&a.position, &a.velocity),
+        id: a.id,
+        position: add(&a.position, &scale(&a.velocity, dt)),
         mass: a.mass,
         velocity: Vec2(a.velocity.0, a.velocity.1),
         force: Vec2(a.force.0, a.force.1),
     }
 }
 
-pub fn reposition_all(a: &[Object]) -> Vec<Object> {
-    a.iter().map(reposition).collect()
+pub fn reposition_all(a: &[Object], dt: f64) -> Vec<Object> {
+    a.iter().map(|o| reposition(o, dt)).collect()
 }
 
 pub fn collide(a: &Object, b: &Object) -> bool {
-    distance(&a.position, &b.position) <= 3.0
+    distance(&a.position, &b.position) <= COLLISION_DISTANCE
 }
 
 pub fn merge(a: &Object, b: &Object) -> Object {
     let mx = a.mass;
     let my = b.mass;
     let merged_mass = mx + my;
-    let s = mx / merged_mass;
     let p1 = &a.position;
     let p2 = &b.position;
-    let uv = unit(&sub(&p1, &p2));
-    let d = scale(&uv, s);
+    // Move towards the lighter body so the centre of mass stays put
+    let d = scale(&sub(p1, p2), my / merged_mass);
     let mv1 = scale(&a.velocity, mx);
     let mv2 = scale(&b.velocity, my);
 
-    let new_position = add(&p1, &d);
+    let new_position = add(p1, &d);
     let new_velocity = scale(&add(&mv1, &mv2), 1.0 / merged_mass);
     let new_force = add(&a.force, &b.force);
 
+    // The merged body keeps the identity of the heavier of the two
+    let id = if mx >= my { a.id } else { b.id };
+
     Object {
+        id,
         position: new_position,
         mass: merged_mass,
         velocity: new_velocity,
@@ -98,38 +268,204 @@ pub fn merge(a: &Object, b: &Object) -> Object {
     }
 }
 
-pub fn collide_all(a: &[Object]) -> Vec<Object> {
+// A perfectly elastic collision: the velocities along the line between the
+// centres are exchanged as if by a spring, and the rest is left alone. Bodies
+// that are already moving apart don't bounce again.
+//
+// The two are also pushed back out to where they touch (keeping the centre of
+// mass still), otherwise gravity that close would fling them apart.
+pub fn bounce(a: &Object, b: &Object) -> (Object, Object) {
+    let n = unit(&sub(&a.position, &b.position));
+    let closing = dot(&sub(&b.velocity, &a.velocity), &n);
+    if closing <= 0.0 {
+        return (*a, *b);
+    }
+
+    let m = a.mass + b.mass;
+    let overlap = (COLLISION_DISTANCE - distance(&a.position, &b.position)).max(0.0);
+    let a_after = Object {
+        position: sub(&scale(&n, overlap * b.mass / m), &a.position),
+        velocity: sub(&scale(&n, 2.0 * b.mass / m * closing), &a.velocity),
+        ..*a
+    };
+    let b_after = Object {
+        position: add(&b.position, &scale(&n, overlap * a.mass / m)),
+        velocity: add(&b.velocity, &scale(&n, 2.0 * a.mass / m * closing)),
+        ..*b
+    };
+
+    (a_after, b_after)
+}
+
+// Break two bodies into `pieces` equal fragments flying apart from their
+// centre of mass. The fragments share the kinetic energy the two had relative
+// to each other, and spread out evenly on a circle so they don't collide again
+// straight away. The first keeps the heavier body's id; the rest are numbered
+// from `next_id`.
+pub fn fragment(a: &Object, b: &Object, pieces: usize, next_id: &mut usize) -> Vec<Object> {
+    let (mass, centre, velocity) = centre_of_mass(&[*a, *b]);
+    let relative = magnitude(&sub(&a.velocity, &b.velocity));
+    let reduced = a.mass * b.mass / mass;
+    let speed = relative * (reduced / mass).sqrt();
+
+    let angle = std::f64::consts::PI / pieces as f64;
+    let radius = COLLISION_DISTANCE / angle.sin();
+    let n = unit(&sub(&a.position, &b.position));
+    let start = n.1.atan2(n.0);
+    let heavier = if a.mass >= b.mass { a.id } else { b.id };
+
+    (0..pieces)
+        .map(|i| {
+            let theta = start + 2.0 * angle * i as f64;
+            let direction = Vec2(theta.cos(), theta.sin());
+            let id = if i == 0 {
+                heavier
+            } else {
+                *next_id += 1;
+                *next_id - 1
+            };
+
+            Object {
+                id,
+                position: add(&centre, &scale(&direction, radius)),
+                mass: mass / pieces as f64,
+                velocity: add(&velocity, &scale(&direction, speed)),
+                force: VEC_ZERO,
+            }
+        })
+        .collect()
+}
+
+// Whatever the collision model makes of two bodies that have collided
+pub fn resolve(a: &Object, b: &Object, collisions: Collisions, next_id: &mut usize) -> Vec<Object> {
+    match collisions {
+        Collisions::Merge => vec![merge(a, b)],
+        Collisions::Bounce => {
+            let (a, b) = bounce(a, b);
+            vec![a, b]
+        }
+        Collisions::Fragment(pieces) => {
+            let mass = a.mass + b.mass;
+            let escape = (2.0 * mass / COLLISION_DISTANCE).sqrt();
+            let impact = magnitude(&sub(&a.velocity, &b.velocity));
+
+            if impact > escape && mass / pieces as f64 >= MIN_FRAGMENT_MASS {
+                fragment(a, b, pieces, next_id)
+            } else {
+                vec![merge(a, b)]
+            }
+        }
+    }
+}
+
+// Where two bodies collided (their centre of mass), and what became of them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Collision {
+    pub position: Vec2,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Merged,
+    Bounced,
+    Fragmented,
+}
+
+// New bodies made by the collisions (fragments) get ids from `next_id` on.
+// Every collision is added to `log`.
+pub fn collide_all(
+    a: &[Object],
+    collisions: Collisions,
+    next_id: &mut usize,
+    log: &mut Vec<Collision>,
+) -> Vec<Object> {
     let mut merged: Vec<Object> = Vec::new();
-    let mut merged_indices: Vec<usize> = Vec::new();
+    // Indexed by body, so checking one doesn't mean searching every collision
+    let mut collided = vec![false; a.len()];
 
     for i in 0..a.len() {
         for j in i + 1..a.len() {
+            // A body can only collide once per step, otherwise it would end
+            // up in two places with its mass counted twice
+            if collided[i] || collided[j] {
+                continue;
+            }
+
             if collide(&a[i], &a[j]) {
-                merged.push(merge(&a[i], &a[j]));
-                merged_indices.push(i);
-                merged_indices.push(j);
+                let resolved = resolve(&a[i], &a[j], collisions, next_id);
+                let outcome = match collisions {
+                    Collisions::Bounce => Outcome::Bounced,
+                    // A fragmenting collision that wasn't hard enough merges
+                    _ if resolved.len() == 1 => Outcome::Merged,
+                    _ => Outcome::Fragmented,
+                };
+                let (_, position, _) = centre_of_mass(&[a[i], a[j]]);
+                log.push(Collision { position, outcome });
+
+                merged.extend(resolved);
+                collided[i] = true;
+                collided[j] = true;
             }
         }
     }
 
-    for i in 0..a.len() {
-        if !merged_indices.contains(&i) {
-            merged.push(a[i]);
+    for (i, obj) in a.iter().enumerate() {
+        if !collided[i] {
+            merged.push(*obj);
         }
     }
 
     merged
 }
 
+// The id after the highest one in use
+pub fn next_free_id(a: &[Object]) -> usize {
+    a.iter().map(|o| o.id + 1).max().unwrap_or(0)
+}
+
 // Don't go down the pipeline route.
 // https://github.com/rust-lang/rfcs/issues/2049
-pub fn update_all(a: &[Object]) -> Vec<Object> {
-    reposition_all(&accelerate_all(&calculate_forces_on_all(&collide_all(&a))))
+// `time` is the simulation time, used by fields that vary over time, and `dt`
+// is how far to advance it.
+//
+// Fragments are numbered from `next_id`, which should be kept from one step
+// to the next so that an id is never reused. Collisions are added to `log`,
+// and each stage is timed into `profile`, if there is one.
+pub fn update_all(
+    a: &[Object],
+    physics: &Physics,
+    time: f64,
+    dt: f64,
+    next_id: &mut usize,
+    log: &mut Vec<Collision>,
+    mut profile: Option<&mut Profile>,
+) -> Vec<Object> {
+    let mut record = |name, start| {
+        if let Some(profile) = profile.as_deref_mut() {
+            profile.record(name, start);
+        }
+    };
+
+    let start = Instant::now();
+    let collided = collide_all(a, physics.collisions, next_id, log);
+    record("collisions", start);
+
+    let start = Instant::now();
+    let forced = calculate_forces_on_all(&collided, physics, time);
+    record("forces", start);
+
+    let start = Instant::now();
+    let moved = reposition_all(&accelerate_all(&forced, dt), dt);
+    record("integrate", start);
+
+    moved
 }
 
 #[test]
 fn test_update_all() {
     let sun = Object {
+        id: 0,
         position: Vec2(512.0, 512.0),
         mass: 300000.0,
         velocity: VEC_ZERO,
@@ -137,19 +473,518 @@ fn test_update_all() {
     };
 
     let obj = Object {
+        id: 1,
         position: VEC_ZERO,
         mass: 1.0,
         velocity: VEC_ZERO,
         force: VEC_ZERO,
     };
 
-    let mut objects: Vec<Object> = Vec::new();
-    objects.push(sun);
-    objects.push(obj);
+    let objects = vec![sun, obj];
 
-    let result = update_all(&objects);
+    let physics = Physics::default();
+    let result = update_all(&objects, &physics, 0.0, 1.0, &mut 2, &mut Vec::new(), None);
 
     // Mass shouldn't change (and I should be able to enforce this with code right?)
     assert_eq!(sun.mass, result[0].mass);
     assert_eq!(obj.mass, result[1].mass);
 }
+
+#[test]
+fn test_has_escaped() {
+    let sun = Object {
+        id: 0,
+        position: VEC_ZERO,
+        mass: 100.0,
+        velocity: VEC_ZERO,
+        force: VEC_ZERO,
+    };
+    let body = |x, vy| Object {
+        id: 1,
+        position: Vec2(x, 0.0),
+        mass: 1e-9,
+        velocity: Vec2(0.0, vy),
+        force: VEC_ZERO,
+    };
+
+    // Escape velocity at r = 200 is sqrt(2 * 100 / 200) = 1
+    let fast = body(200.0, 1.1);
+    let slow = body(200.0, 0.9);
+    let near = body(50.0, 1.9);
+
+    assert!(has_escaped(&fast, &[sun, fast], 100.0));
+    assert!(!has_escaped(&slow, &[sun, slow], 100.0));
+    assert!(!has_escaped(&near, &[sun, near], 100.0));
+
+    let system = [sun, fast];
+    assert_eq!(vec![&fast], escaped(&system, 100.0).collect::<Vec<_>>());
+}
+
+// Orbit a light planet around a heavy sun for a number of orbits, and return
+// how far the angle of closest approach moved per orbit.
+#[cfg(test)]
+fn periapsis_advance(speed_of_light: Option<f64>, orbits: usize, dt: f64) -> f64 {
+    let sun = Object {
+        id: 0,
+        position: VEC_ZERO,
+        mass: 1000.0,
+        velocity: VEC_ZERO,
+        force: VEC_ZERO,
+    };
+    // Start at periapsis r = 50 of an orbit with a = 100, e = 0.5
+    let planet = Object {
+        id: 1,
+        position: Vec2(50.0, 0.0),
+        mass: 1e-6,
+        velocity: Vec2(0.0, 30.0f64.sqrt()),
+        force: VEC_ZERO,
+    };
+    let physics = Physics {
+        speed_of_light,
+        ..Physics::default()
+    };
+
+    // Angles of each closest approach seen, which includes one right at the
+    // start (the integrator dips just inside r = 50 on the first step)
+    let mut objects = vec![sun, planet];
+    let mut angles = Vec::new();
+    let (mut before, mut last, mut last_angle) = (f64::MAX, 50.0, 0.0);
+    let mut time = 0.0;
+    let mut next_id = next_free_id(&objects);
+
+    while angles.len() <= orbits {
+        objects = update_all(
+            &objects,
+            &physics,
+            time,
+            dt,
+            &mut next_id,
+            &mut Vec::new(),
+            None,
+        );
+        time += dt;
+
+        let r = sub(&objects[0].position, &objects[1].position);
+        let now = magnitude(&r);
+        if last < before && last < now {
+            angles.push(last_angle);
+        }
+
+        before = last;
+        last = now;
+        last_angle = r.1.atan2(r.0);
+    }
+
+    (angles[orbits] - angles[0]) / orbits as f64
+}
+
+#[test]
+fn test_post_newtonian_precession() {
+    let (m, a, e, c): (f64, f64, f64, f64) = (1000.0, 100.0, 0.5, 70.0);
+    let expected = 6.0 * std::f64::consts::PI * m / (c * c * a * (1.0 - e * e));
+
+    // The integrator precesses a little by itself, so measure against that
+    let newtonian = periapsis_advance(None, 4, 0.01);
+    let relativistic = periapsis_advance(Some(c), 4, 0.01);
+    let measured = relativistic - newtonian;
+
+    assert!(
+        (measured - expected).abs() < 0.05 * expected,
+        "expected {} radians per orbit, measured {}",
+        expected,
+        measured
+    );
+}
+
+// Two bodies about their common centre of mass at the origin, with their
+// separation along the x axis and relative velocity along y, as at periapsis.
+#[cfg(test)]
+fn two_body(m1: f64, m2: f64, separation: f64, speed: f64) -> Vec<Object> {
+    let m = m1 + m2;
+    let body = |id, mass, share: f64| Object {
+        id,
+        position: Vec2(separation * share, 0.0),
+        mass,
+        velocity: Vec2(0.0, speed * share),
+        force: VEC_ZERO,
+    };
+
+    vec![body(0, m1, -m2 / m), body(1, m2, m1 / m)]
+}
+
+// Where the second body is relative to the first at time `t`, from Kepler's
+// equation for an orbit starting at periapsis on the x axis.
+#[cfg(test)]
+fn kepler_position(mass: f64, a: f64, e: f64, t: f64) -> Vec2 {
+    let mean_anomaly = (mass / a.powi(3)).sqrt() * t;
+
+    // Solve E - e sin E = M by Newton's method
+    let mut ecc_anomaly = mean_anomaly;
+    for _ in 0..50 {
+        ecc_anomaly -=
+            (ecc_anomaly - e * ecc_anomaly.sin() - mean_anomaly) / (1.0 - e * ecc_anomaly.cos());
+    }
+
+    Vec2(
+        a * (ecc_anomaly.cos() - e),
+        a * (1.0 - e * e).sqrt() * ecc_anomaly.sin(),
+    )
+}
+
+// Step a two body system for `periods` orbits, checking the separation
+// against Kepler at every step and returning the largest error.
+#[cfg(test)]
+fn kepler_error(m1: f64, m2: f64, a: f64, e: f64, periods: f64, dt: f64) -> f64 {
+    let m = m1 + m2;
+    let periapsis = a * (1.0 - e);
+    let speed = (m * (1.0 + e) / periapsis).sqrt();
+    let period = 2.0 * std::f64::consts::PI * (a.powi(3) / m).sqrt();
+    let physics = Physics::default();
+
+    let mut objects = two_body(m1, m2, periapsis, speed);
+    let mut worst: f64 = 0.0;
+    let steps = (periods * period / dt).round() as usize;
+    let mut next_id = next_free_id(&objects);
+
+    for step in 0..steps {
+        objects = update_all(
+            &objects,
+            &physics,
+            step as f64 * dt,
+            dt,
+            &mut next_id,
+            &mut Vec::new(),
+            None,
+        );
+
+        // Positions are updated with the velocity at the end of the step,
+        // so they're a step ahead of the velocities
+        let t = (step + 1) as f64 * dt;
+        let r = sub(&objects[0].position, &objects[1].position);
+        worst = worst.max(distance(&r, &kepler_position(m, a, e, t)));
+    }
+
+    worst
+}
+
+#[test]
+fn test_circular_orbit_matches_kepler() {
+    // Ten orbits of two comparable masses, 100 apart
+    let error = kepler_error(600.0, 400.0, 100.0, 0.0, 10.0, 0.01);
+
+    assert!(error < 0.2, "drifted {} from the circular orbit", error);
+}
+
+#[test]
+fn test_elliptical_orbit_matches_kepler() {
+    let error = kepler_error(700.0, 300.0, 100.0, 0.5, 10.0, 0.01);
+
+    assert!(error < 0.5, "drifted {} from the Kepler ellipse", error);
+}
+
+#[test]
+fn test_two_body_conserves_momentum_and_energy() {
+    let physics = Physics::default();
+    let mut objects = two_body(700.0, 300.0, 50.0, 30.0f64.sqrt());
+    let energy = total_energy(&objects);
+    let mut next_id = next_free_id(&objects);
+
+    for step in 0..20000 {
+        objects = update_all(
+            &objects,
+            &physics,
+            step as f64 * 0.01,
+            0.01,
+            &mut next_id,
+            &mut Vec::new(),
+            None,
+        );
+
+        let (_, position, velocity) = centre_of_mass(&objects);
+        assert!(magnitude(&position) < 1e-9 && magnitude(&velocity) < 1e-12);
+        // Symplectic Euler wobbles but doesn't drift
+        assert!(((total_energy(&objects) - energy) / energy).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn test_merge_conserves_mass_and_momentum() {
+    let a = Object {
+        id: 3,
+        position: Vec2(10.0, 20.0),
+        mass: 3.0,
+        velocity: Vec2(1.0, -2.0),
+        force: VEC_ZERO,
+    };
+    let b = Object {
+        id: 4,
+        position: Vec2(12.0, 20.0),
+        mass: 1.0,
+        velocity: Vec2(-3.0, 2.0),
+        force: VEC_ZERO,
+    };
+
+    let merged = merge(&a, &b);
+
+    assert_eq!(3, merged.id);
+    assert_eq!(4.0, merged.mass);
+    assert_eq!(Vec2(0.0, -1.0), merged.velocity);
+    assert_eq!(Vec2(10.5, 20.0), merged.position);
+    assert_eq!(merged, merge(&b, &a));
+}
+
+#[test]
+fn test_collide_all_merges_each_body_once() {
+    let body = |id, x| Object {
+        id,
+        position: Vec2(x, 0.0),
+        mass: 1.0,
+        velocity: VEC_ZERO,
+        force: VEC_ZERO,
+    };
+
+    // The middle body touches both the others
+    let bodies = [body(0, 0.0), body(1, 2.0), body(2, 4.0)];
+    let mut log = Vec::new();
+    let result = collide_all(&bodies, Collisions::Merge, &mut 3, &mut log);
+
+    assert_eq!(2, result.len());
+    assert_eq!(3.0, result.iter().map(|o| o.mass).sum::<f64>());
+    assert_eq!(
+        vec![Collision {
+            position: Vec2(1.0, 0.0),
+            outcome: Outcome::Merged
+        }],
+        log
+    );
+}
+
+#[test]
+fn test_newtons_third_law() {
+    let a = Object {
+        id: 0,
+        position: Vec2(1.0, 2.0),
+        mass: 8.0,
+        velocity: VEC_ZERO,
+        force: VEC_ZERO,
+    };
+    let b = Object {
+        id: 1,
+        position: Vec2(1.0, 6.0),
+        mass: 2.0,
+        velocity: VEC_ZERO,
+        force: VEC_ZERO,
+    };
+
+    // |F| = 8 * 2 / 4^2, pointing from b back towards a
+    assert_eq!(Vec2(0.0, -1.0), force_between(&a, &b));
+    assert_eq!(Vec2(0.0, 1.0), force_between(&b, &a));
+}
+
+#[test]
+fn test_parse_collisions() {
+    assert_eq!(Some(Collisions::Merge), parse_collisions("merge"));
+    assert_eq!(Some(Collisions::Bounce), parse_collisions("bounce"));
+    assert_eq!(Some(Collisions::Fragment(4)), parse_collisions("fragment"));
+    assert_eq!(
+        Some(Collisions::Fragment(8)),
+        parse_collisions("fragment:8")
+    );
+    assert_eq!(None, parse_collisions("fragment:1"));
+    assert_eq!(None, parse_collisions("fragment:"));
+    assert_eq!(None, parse_collisions("splat"));
+}
+
+#[test]
+fn test_bounce_head_on() {
+    let body = |id, x, vx| Object {
+        id,
+        position: Vec2(x, 0.0),
+        mass: 2.0,
+        velocity: Vec2(vx, 1.0),
+        force: VEC_ZERO,
+    };
+    let (a, b) = (body(0, 0.0, 3.0), body(1, 2.0, -1.0));
+
+    // Equal masses swap their velocities along the line between them, and
+    // keep the rest
+    let (a_after, b_after) = bounce(&a, &b);
+    assert_eq!(Vec2(-1.0, 1.0), a_after.velocity);
+    assert_eq!(Vec2(3.0, 1.0), b_after.velocity);
+    // Pushed apart to just touching
+    assert_eq!(Vec2(-0.5, 0.0), a_after.position);
+    assert_eq!(Vec2(2.5, 0.0), b_after.position);
+
+    // Once they're moving apart, they're left alone
+    assert_eq!((a_after, b_after), bounce(&a_after, &b_after));
+}
+
+#[test]
+fn test_fragment_conserves_mass_and_momentum() {
+    let a = Object {
+        id: 2,
+        position: Vec2(100.0, 100.0),
+        mass: 3.0,
+        velocity: Vec2(4.0, 0.0),
+        force: VEC_ZERO,
+    };
+    let b = Object {
+        id: 5,
+        position: Vec2(102.0, 100.0),
+        mass: 1.0,
+        velocity: Vec2(-4.0, 1.0),
+        force: VEC_ZERO,
+    };
+    let mut next_id = 10;
+
+    let pieces = resolve(&a, &b, Collisions::Fragment(4), &mut next_id);
+
+    let ids: Vec<usize> = pieces.iter().map(|o| o.id).collect();
+    assert_eq!(vec![2, 10, 11, 12], ids);
+    assert_eq!(13, next_id);
+
+    let (mass, centre, _) = centre_of_mass(&pieces);
+    assert_eq!(4.0, mass);
+    assert!(distance(&centre, &Vec2(100.5, 100.0)) < 1e-9);
+    assert!(distance(&momentum(&pieces), &momentum(&[a, b])) < 1e-9);
+
+    // None of the pieces are touching
+    for (i, x) in pieces.iter().enumerate() {
+        assert!(pieces[i + 1..].iter().all(|y| !collide(x, y)));
+    }
+}
+
+#[test]
+fn test_fragment_slow_impacts_merge() {
+    let body = |id, x, vx| Object {
+        id,
+        position: Vec2(x, 0.0),
+        mass: 6.0,
+        velocity: Vec2(vx, 0.0),
+        force: VEC_ZERO,
+    };
+    // Escape velocity at contact is sqrt(2 * 12 / 3), a little under 3
+    let slow = resolve(
+        &body(0, 0.0, 1.0),
+        &body(1, 2.0, -1.0),
+        Collisions::Fragment(4),
+        &mut 2,
+    );
+    let fast = resolve(
+        &body(0, 0.0, 2.0),
+        &body(1, 2.0, -2.0),
+        Collisions::Fragment(4),
+        &mut 2,
+    );
+
+    assert_eq!(1, slow.len());
+    assert_eq!(4, fast.len());
+}
+
+#[cfg(test)]
+fn arbitrary_object(id: usize) -> impl proptest::strategy::Strategy<Value = Object> {
+    use proptest::prelude::*;
+
+    (0.0..1000.0, 0.0..1000.0, 0.1..100.0, -5.0..5.0, -5.0..5.0).prop_map(
+        move |(x, y, mass, vx, vy)| Object {
+            id,
+            position: Vec2(x, y),
+            mass,
+            velocity: Vec2(vx, vy),
+            force: VEC_ZERO,
+        },
+    )
+}
+
+#[cfg(test)]
+fn arbitrary_objects() -> impl proptest::strategy::Strategy<Value = Vec<Object>> {
+    use proptest::prelude::*;
+
+    (1..12usize).prop_flat_map(|n| (0..n).map(arbitrary_object).collect::<Vec<_>>())
+}
+
+#[cfg(test)]
+fn arbitrary_collisions() -> impl proptest::strategy::Strategy<Value = Collisions> {
+    use proptest::prelude::*;
+
+    prop_oneof![
+        Just(Collisions::Merge),
+        Just(Collisions::Bounce),
+        (2..8usize).prop_map(Collisions::Fragment),
+    ]
+}
+
+#[cfg(test)]
+fn momentum(a: &[Object]) -> Vec2 {
+    a.iter()
+        .fold(VEC_ZERO, |acc, o| add(&acc, &scale(&o.velocity, o.mass)))
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn prop_force_between_is_antisymmetric(a in arbitrary_object(0), b in arbitrary_object(1)) {
+        let ab = force_between(&a, &b);
+        let ba = force_between(&b, &a);
+
+        proptest::prop_assert_eq!(Vec2(-ab.0, -ab.1), ba);
+    }
+
+    #[test]
+    fn prop_merge_conserves_mass_momentum_and_centre(
+        a in arbitrary_object(0),
+        b in arbitrary_object(1),
+    ) {
+        let merged = merge(&a, &b);
+        let (mass, position, _) = centre_of_mass(&[a, b]);
+        let p = momentum(&[a, b]);
+
+        proptest::prop_assert!((merged.mass - mass).abs() < 1e-9);
+        proptest::prop_assert!(distance(&momentum(&[merged]), &p) < 1e-9 * mass);
+        proptest::prop_assert!(distance(&merged.position, &position) < 1e-9);
+    }
+
+    #[test]
+    fn prop_collisions_conserve_mass_and_momentum(
+        a in arbitrary_object(0),
+        b in arbitrary_object(1),
+        angle in 0.0..std::f64::consts::TAU,
+        collisions in arbitrary_collisions(),
+    ) {
+        // Put b touching a
+        let b = Object {
+            position: add(&a.position, &Vec2(2.0 * angle.cos(), 2.0 * angle.sin())),
+            ..b
+        };
+        let (mass, _, _) = centre_of_mass(&[a, b]);
+
+        let after = resolve(&a, &b, collisions, &mut 2);
+
+        let (mass_after, _, _) = centre_of_mass(&after);
+        proptest::prop_assert!((mass_after - mass).abs() < 1e-9 * mass);
+        proptest::prop_assert!(distance(&momentum(&after), &momentum(&[a, b])) < 1e-9 * mass);
+    }
+
+    #[test]
+    fn prop_update_all_conserves_mass_and_momentum(
+        objects in arbitrary_objects(),
+        collisions in arbitrary_collisions(),
+    ) {
+        let physics = Physics {
+            collisions,
+            ..Physics::default()
+        };
+        let (mass, _, _) = centre_of_mass(&objects);
+        let p = momentum(&objects);
+
+        let mut after = objects.clone();
+        let mut next_id = next_free_id(&objects);
+        for step in 0..10 {
+            after = update_all(&after, &physics, step as f64, 1.0, &mut next_id, &mut Vec::new(), None);
+        }
+
+        let (mass_after, _, _) = centre_of_mass(&after);
+        proptest::prop_assert!((mass_after - mass).abs() < 1e-9 * mass);
+        proptest::prop_assert!(distance(&momentum(&after), &p) < 1e-9 * mass);
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+// Time spent in each stage of the pipeline, in the order they first ran
+#[derive(Default)]
+pub struct Profile {
+    stages: Vec<Stage>,
+}
+
+struct Stage {
+    name: &'static str,
+    total: Duration,
+    calls: u32,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    // Add the time since `start` to a stage
+    pub fn record(&mut self, name: &'static str, start: Instant) {
+        self.add(name, start.elapsed());
+    }
+
+    pub fn add(&mut self, name: &'static str, time: Duration) {
+        match self.stages.iter_mut().find(|s| s.name == name) {
+            Some(stage) => {
+                stage.total += time;
+                stage.calls += 1;
+            }
+            None => self.stages.push(Stage {
+                name,
+                total: time,
+                calls: 1,
+            }),
+        }
+    }
+
+    // Each stage's name and total time
+    pub fn stages(&self) -> impl Iterator<Item = (&'static str, Duration)> + '_ {
+        self.stages.iter().map(|s| (s.name, s.total))
+    }
+
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|s| s.total).sum()
+    }
+
+    /// A table of each stage's total time, average time per call and share
+    /// of the whole run.
+    pub fn report(&self) -> String {
+        let total = self.total().as_secs_f64();
+        let mut report = String::new();
+
+        // Writing to a String can't fail
+        writeln!(
+            report,
+            "{:<12} {:>12} {:>8} {:>12} {:>7}",
+            "stage", "total ms", "calls", "per call us", "share"
+        )
+        .unwrap();
+
+        for stage in &self.stages {
+            let secs = stage.total.as_secs_f64();
+            writeln!(
+                report,
+                "{:<12} {:>12.3} {:>8} {:>12.3} {:>6.1}%",
+                stage.name,
+                secs * 1e3,
+                stage.calls,
+                secs * 1e6 / stage.calls as f64,
+                if total > 0.0 {
+                    secs / total * 100.0
+                } else {
+                    0.0
+                }
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "{:<12} {:>12.3}", "total", total * 1e3).unwrap();
+        report
+    }
+}
+
+#[test]
+fn test_profile_report() {
+    let mut profile = Profile::new();
+    profile.add("forces", Duration::from_millis(3));
+    profile.add("render", Duration::from_millis(2));
+    profile.add("forces", Duration::from_millis(3));
+
+    assert_eq!(Duration::from_millis(8), profile.total());
+
+    let report = profile.report();
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(4, lines.len());
+    assert!(lines[1].starts_with("forces"));
+    assert!(lines[1].contains("6.000") && lines[1].contains("3000.000"));
+    assert!(lines[1].ends_with("75.0%"));
+    assert!(lines[3].starts_with("total") && lines[3].ends_with("8.000"));
+}
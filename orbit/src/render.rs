@@ -1,26 +1,287 @@
 use super::object::*;
+use super::vec2::*;
 
-pub struct RGBA(u8, u8, u8, u8);
+pub struct Rgba(pub u8, pub u8, pub u8, pub u8);
+
+const TEXT: Rgba = Rgba(255, 255, 255, 255);
+const LABEL: Rgba = Rgba(160, 200, 255, 255);
+
+// Glyphs are 5x7 pixels; each is drawn scaled up with a one pixel gap.
+const GLYPH_WIDTH: i32 = 5;
+const GLYPH_HEIGHT: i32 = 7;
+const OVERLAY_SCALE: i32 = 2;
+const MARGIN: i32 = 8;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+pub fn parse_corner(s: &str) -> Option<Corner> {
+    match s {
+        "top-left" => Some(Corner::TopLeft),
+        "top-right" => Some(Corner::TopRight),
+        "bottom-left" => Some(Corner::BottomLeft),
+        "bottom-right" => Some(Corner::BottomRight),
+        _ => None,
+    }
+}
+
+// How bodies are drawn. Circles show every body individually, which turns
+// into noise with thousands of them, so the other modes bin bodies into
+// square cells and show the density or the mean velocity of each cell.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum RenderMode {
+    Circles,
+    Density(Weighting),
+    Velocity,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Weighting {
+    Mass,
+    Count,
+}
+
+pub fn parse_render_mode(s: &str) -> Option<RenderMode> {
+    match s {
+        "circles" => Some(RenderMode::Circles),
+        "density" | "density:mass" => Some(RenderMode::Density(Weighting::Mass)),
+        "density:count" => Some(RenderMode::Density(Weighting::Count)),
+        "velocity" => Some(RenderMode::Velocity),
+        _ => None,
+    }
+}
+
+// A perceptually ordered map from 0..1 to black, purple, red, orange, yellow
+pub fn colour_map(t: f64) -> Rgba {
+    const STOPS: [(f64, f64, f64); 5] = [
+        (0.0, 0.0, 4.0),
+        (87.0, 16.0, 110.0),
+        (188.0, 55.0, 84.0),
+        (249.0, 142.0, 9.0),
+        (252.0, 255.0, 164.0),
+    ];
+
+    let t = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f64;
+    let i = (t as usize).min(STOPS.len() - 2);
+    let f = t - i as f64;
+    let (a, b) = (STOPS[i], STOPS[i + 1]);
+    let mix = |x: f64, y: f64| (x + (y - x) * f).round() as u8;
+
+    Rgba(mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2), 255)
+}
+
+// Maps simulation space onto a square image `size` pixels across, with
+// `centre` in the middle and `zoom` pixels per unit of space.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Camera {
+    pub centre: Vec2,
+    pub zoom: f64,
+    pub size: usize,
+}
+
+impl Camera {
+    // A camera that shows the whole of a square space of the given size
+    pub fn fit(space_size: f64, size: usize) -> Camera {
+        Camera {
+            centre: Vec2(space_size / 2.0, space_size / 2.0),
+            zoom: size as f64 / space_size,
+            size,
+        }
+    }
+
+    pub fn project(&self, p: &Vec2) -> Vec2 {
+        let half = self.size as f64 / 2.0;
+        Vec2(
+            (p.0 - self.centre.0) * self.zoom + half,
+            (p.1 - self.centre.1) * self.zoom + half,
+        )
+    }
+}
+
+// Each body gets its own colour, spread around the hue wheel by the golden
+// ratio so that neighbouring ids look different. The sun is always yellow.
+pub fn body_colour(id: usize) -> Rgba {
+    if id == 0 {
+        return Rgba(255, 220, 80, 255);
+    }
+
+    let hue = (id as f64 * 0.618_033_988_75).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    // Keep the colours light enough to see on black
+    let channel = |c: f64| (95.0 + 160.0 * c) as u8;
+
+    Rgba(channel(r), channel(g), channel(b), 255)
+}
 
 pub struct Frame {
     pub values: Vec<u8>,
     pub size: usize,
+    pub camera: Camera,
 }
 
 impl Frame {
+    // A black frame the size of the camera's image
+    pub fn new(camera: Camera) -> Frame {
+        Frame {
+            values: [0, 0, 0, 255].repeat(camera.size * camera.size),
+            size: camera.size,
+            camera,
+        }
+    }
+
+    pub fn render_all(&mut self, objects: &[Object], mode: RenderMode, bin_size: usize) {
+        match mode {
+            RenderMode::Circles => {
+                for object in objects {
+                    self.render(object);
+                }
+            }
+            RenderMode::Density(weighting) => self.render_density(objects, weighting, bin_size),
+            RenderMode::Velocity => self.render_velocity(objects, bin_size),
+        }
+    }
+
     pub fn render(&mut self, obj: &Object) {
-        let x = obj.position.0 as usize;
-        let y = obj.position.1 as usize;
+        let p = self.camera.project(&obj.position);
+        let x = p.0 as usize;
+        let y = p.1 as usize;
+
+        let weight = obj.mass * self.camera.zoom;
+        self.draw_circle(x, y, (weight / 2.0) as i32, &Rgba(255, 255, 255, 255));
+    }
+
+    // Put the name of the body just to the bottom right of it
+    pub fn render_label(&mut self, obj: &Object) {
+        let p = self.camera.project(&obj.position);
+        let offset = (obj.mass * self.camera.zoom / 2.0) as i32 + 3;
+        let x = p.0 as i32 + offset;
+        let y = p.1 as i32 + offset;
+
+        self.draw_text(x, y, &body_name(obj.id), 1, &LABEL);
+    }
+
+    // Draw a block of text lines tucked into one corner of the frame
+    pub fn render_overlay(&mut self, corner: Corner, lines: &[String]) {
+        let advance = (GLYPH_WIDTH + 1) * OVERLAY_SCALE;
+        let line_height = (GLYPH_HEIGHT + 2) * OVERLAY_SCALE;
+        let size = self.size as i32;
+        let top = match corner {
+            Corner::TopLeft | Corner::TopRight => MARGIN,
+            Corner::BottomLeft | Corner::BottomRight => {
+                size - MARGIN - line_height * lines.len() as i32
+            }
+        };
+
+        for (i, line) in lines.iter().enumerate() {
+            let width = advance * line.chars().count() as i32;
+            let x = match corner {
+                Corner::TopLeft | Corner::BottomLeft => MARGIN,
+                Corner::TopRight | Corner::BottomRight => size - MARGIN - width,
+            };
+
+            self.draw_text(x, top + i as i32 * line_height, line, OVERLAY_SCALE, &TEXT);
+        }
+    }
+
+    // Fill each cell with the colour of its (log scaled) mass or body count
+    fn render_density(&mut self, objects: &[Object], weighting: Weighting, bin_size: usize) {
+        let cells = self.bin(objects, bin_size);
+        let max = cells
+            .iter()
+            .map(|c| c.weight(weighting))
+            .fold(0.0, f64::max);
+        if max == 0.0 {
+            return;
+        }
+
+        let cells_across = self.size.div_ceil(bin_size);
+        for (i, cell) in cells.iter().enumerate() {
+            let weight = cell.weight(weighting);
+            if weight > 0.0 {
+                let t = (1.0 + weight).ln() / (1.0 + max).ln();
+                let (x, y) = ((i % cells_across) * bin_size, (i / cells_across) * bin_size);
+                let b = bin_size as i32;
+                self.fill_rect(x as i32, y as i32, b, b, &colour_map(t));
+            }
+        }
+    }
+
+    // Draw an arrow from the middle of each occupied cell along the mean
+    // velocity there, scaled so the fastest cell's arrow fills its cell and
+    // coloured by speed.
+    fn render_velocity(&mut self, objects: &[Object], bin_size: usize) {
+        let cells = self.bin(objects, bin_size);
+        let speed = |c: &Cell| magnitude(&c.mean_velocity());
+        let max = cells.iter().map(speed).fold(0.0, f64::max);
+        if max == 0.0 {
+            return;
+        }
+
+        let cells_across = self.size.div_ceil(bin_size);
+        let length = bin_size as f64 * 0.9;
+        for (i, cell) in cells.iter().enumerate() {
+            if cell.count == 0 {
+                continue;
+            }
+
+            let half = bin_size as f64 / 2.0;
+            let from = Vec2(
+                ((i % cells_across) * bin_size) as f64 + half,
+                ((i / cells_across) * bin_size) as f64 + half,
+            );
+            let v = scale(&cell.mean_velocity(), length / max);
+            let to = add(&from, &v);
+            let colour = colour_map(0.25 + 0.75 * speed(cell) / max);
 
-        let weight = obj.mass;
-        self.draw_circle(x, y, (weight / 2.0) as i32, &RGBA(255, 255, 255, 255));
+            self.draw_line(&from, &to, &colour);
+            // Arrow head: two short strokes back from the tip
+            let back = scale(&unit(&v), -0.3 * magnitude(&v).max(3.0));
+            let side = scale(&rotate90(&back), 0.5);
+            self.draw_line(&to, &add(&to, &add(&back, &side)), &colour);
+            self.draw_line(&to, &add(&to, &sub(&side, &back)), &colour);
+        }
+    }
+
+    // Bin the bodies on screen into square cells `bin_size` pixels across,
+    // row by row
+    fn bin(&self, objects: &[Object], bin_size: usize) -> Vec<Cell> {
+        let cells_across = self.size.div_ceil(bin_size);
+        let mut cells = vec![Cell::default(); cells_across * cells_across];
+
+        for obj in objects {
+            let p = self.camera.project(&obj.position);
+            if p.0 < 0.0 || p.1 < 0.0 || p.0 >= self.size as f64 || p.1 >= self.size as f64 {
+                continue;
+            }
+
+            let cell =
+                &mut cells[(p.1 as usize / bin_size) * cells_across + p.0 as usize / bin_size];
+            cell.count += 1;
+            cell.mass += obj.mass;
+            cell.velocity = add(&cell.velocity, &obj.velocity);
+        }
+
+        cells
     }
 
     pub fn values(&self) -> &Vec<u8> {
         &self.values
     }
 
-    fn draw_circle(&mut self, xc: usize, yc: usize, radius: i32, pixel: &RGBA) {
+    fn draw_circle(&mut self, xc: usize, yc: usize, radius: i32, pixel: &Rgba) {
         let mut x: i32 = 0;
         let mut y: i32 = radius;
         let mut d: i32 = 3 - 2 * radius;
@@ -39,7 +300,7 @@ impl Frame {
     }
 
     // usize, but needing negatives results in daftness. Sorry everyone.
-    fn draw_circle_int(&mut self, xc: usize, yc: usize, x: i32, y: i32, pixel: &RGBA) {
+    fn draw_circle_int(&mut self, xc: usize, yc: usize, x: i32, y: i32, pixel: &Rgba) {
         let xpos = (xc as i32 + x) as usize;
         let ypos = (yc as i32 + y) as usize;
         let xneg = (xc as i32 - x) as usize;
@@ -61,11 +322,62 @@ impl Frame {
         self.set_pixel(xneg_, yneg_, pixel);
     }
 
+    fn draw_text(&mut self, x: i32, y: i32, text: &str, scale: i32, pixel: &Rgba) {
+        for (i, c) in text.chars().enumerate() {
+            let left = x + i as i32 * (GLYPH_WIDTH + 1) * scale;
+
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - col)) != 0 {
+                        let top = y + row as i32 * scale;
+                        self.fill_rect(left + col * scale, top, scale, scale, pixel);
+                    }
+                }
+            }
+        }
+    }
+
+    // Bresenham's line algorithm
+    fn draw_line(&mut self, from: &Vec2, to: &Vec2, pixel: &Rgba) {
+        let (mut x, mut y) = (from.0.round() as i32, from.1.round() as i32);
+        let (x1, y1) = (to.0.round() as i32, to.1.round() as i32);
+        let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+        let (sx, sy) = ((x1 - x).signum(), (y1 - y).signum());
+        let mut err = dx + dy;
+
+        loop {
+            self.fill_rect(x, y, 1, 1, pixel);
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    // Unlike the circles, text can go off any edge so clip it properly
+    fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, pixel: &Rgba) {
+        let size = self.size as i32;
+        for py in y.max(0)..(y + height).min(size) {
+            for px in x.max(0)..(x + width).min(size) {
+                self.set_pixel(px as usize, py as usize, pixel);
+            }
+        }
+    }
+
     fn offset(&self, x: usize, y: usize) -> usize {
         (4usize) * x + (y * self.size * 4usize)
     }
 
-    fn set_pixel(&mut self, x: usize, y: usize, pixel: &RGBA) {
+    fn set_pixel(&mut self, x: usize, y: usize, pixel: &Rgba) {
         let array_pos = self.offset(x, y);
 
         if array_pos + 3 <= self.values.len() {
@@ -76,3 +388,161 @@ impl Frame {
         }
     }
 }
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Cell {
+    count: usize,
+    mass: f64,
+    velocity: Vec2,
+}
+
+impl Cell {
+    fn weight(&self, weighting: Weighting) -> f64 {
+        match weighting {
+            Weighting::Mass => self.mass,
+            Weighting::Count => self.count as f64,
+        }
+    }
+
+    fn mean_velocity(&self) -> Vec2 {
+        if self.count == 0 {
+            VEC_ZERO
+        } else {
+            scale(&self.velocity, 1.0 / self.count as f64)
+        }
+    }
+}
+
+// A tiny built-in 5x7 bitmap font. Each row is a bitmask, with the most
+// significant of the five bits on the left. Only upper case is provided, so
+// lower case is drawn as upper case.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '=' => [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+#[test]
+fn test_render_overlay_stays_in_corner() {
+    let size = 64;
+    let mut frame = Frame::new(Camera::fit(size as f64, size));
+
+    frame.render_overlay(Corner::BottomRight, &[String::from("1")]);
+
+    let lit: Vec<(usize, usize)> = (0..size * size)
+        .filter(|i| frame.values[i * 4] == 255)
+        .map(|i| (i % size, i / size))
+        .collect();
+
+    assert!(!lit.is_empty());
+    assert!(lit.iter().all(|&(x, y)| x >= size / 2 && y >= size / 2));
+}
+
+#[test]
+fn test_camera() {
+    let camera = Camera::fit(100.0, 200);
+
+    assert_eq!(Vec2(0.0, 0.0), camera.project(&VEC_ZERO));
+    assert_eq!(Vec2(100.0, 100.0), camera.project(&Vec2(50.0, 50.0)));
+
+    let zoomed = Camera {
+        zoom: 4.0,
+        ..camera
+    };
+    assert_eq!(Vec2(120.0, 60.0), zoomed.project(&Vec2(55.0, 40.0)));
+}
+
+#[cfg(test)]
+fn at(x: f64, y: f64, mass: f64, velocity: Vec2) -> Object {
+    Object {
+        id: 1,
+        position: Vec2(x, y),
+        mass,
+        velocity,
+        force: VEC_ZERO,
+    }
+}
+
+#[test]
+fn test_render_density() {
+    let size = 16;
+    let mut frame = Frame::new(Camera::fit(size as f64, size));
+    let objects = [
+        at(1.0, 1.0, 1.0, VEC_ZERO),
+        at(2.0, 2.0, 1.0, VEC_ZERO),
+        at(12.0, 12.0, 1.0, VEC_ZERO),
+    ];
+
+    frame.render_all(&objects, RenderMode::Density(Weighting::Count), 8);
+
+    let pixel = |x: usize, y: usize| frame.values[(y * size + x) * 4..][..3].to_vec();
+    // The busiest cell gets the top of the colour map, empty ones stay black
+    assert_eq!(vec![252, 255, 164], pixel(0, 7));
+    assert_eq!(vec![0, 0, 0], pixel(15, 0));
+    let quieter = pixel(15, 15);
+    assert!(quieter != vec![0, 0, 0] && quieter != pixel(0, 7));
+}
+
+#[test]
+fn test_render_velocity() {
+    let size = 32;
+    let mut frame = Frame::new(Camera::fit(size as f64, size));
+
+    frame.render_all(
+        &[at(4.0, 4.0, 1.0, Vec2(1.0, 0.0))],
+        RenderMode::Velocity,
+        16,
+    );
+
+    let lit = |x: usize, y: usize| frame.values[(y * size + x) * 4] != 0;
+    // An arrow pointing right from the middle of the only cell
+    assert!(lit(8, 8) && lit(15, 8) && lit(22, 8));
+    assert!(!lit(2, 8));
+}
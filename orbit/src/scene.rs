@@ -6,11 +6,14 @@ use rand::prelude::*;
 pub struct Scene {
     pub space_size: u32,
     pub num_objects: u32,
+    // The same seed always creates the same scene
+    pub seed: u64,
 }
 
 impl Scene {
     fn sun(&self) -> Object {
         Object {
+            id: 0,
             position: Vec2(self.space_size as f64 / 2.0, self.space_size as f64 / 2.0),
             mass: 30.0,
             velocity: VEC_ZERO,
@@ -19,14 +22,15 @@ impl Scene {
     }
 
     fn random_velocity(&self, r: f64, pos: &Vec2) -> Vec2 {
-        let sun_direction = unit(&sub(&pos, &self.sun().position));
+        let sun_direction = unit(&sub(pos, &self.sun().position));
         let direction = rotate90(&sun_direction);
         scale(&direction, r * 0.3 + 0.3)
     }
 
-    fn random_object(&self, (mass, vel, a, b): (f64, f64, f64, f64)) -> Object {
+    fn random_object(&self, id: usize, (mass, vel, a, b): (f64, f64, f64, f64)) -> Object {
         let p = self.random_position(a, b);
         Object {
+            id,
             position: Vec2(p.0, p.1),
             mass: mass * 0.2,
             velocity: self.random_velocity(vel, &p),
@@ -45,18 +49,31 @@ impl Scene {
 
     pub fn create(&self) -> Vec<Object> {
         let mut objects: Vec<Object> = vec![self.sun()];
+        let mut rng = StdRng::seed_from_u64(self.seed);
 
-        for _i in 0..self.num_objects {
+        for i in 0..self.num_objects {
             let x: (f64, f64, f64, f64) = (
-                rand::thread_rng().gen::<f64>(), // mass
-                rand::thread_rng().gen::<f64>(), // velo
-                rand::thread_rng().gen::<f64>(), // pos x
-                rand::thread_rng().gen::<f64>(), // pos y
+                rng.gen::<f64>(), // mass
+                rng.gen::<f64>(), // velo
+                rng.gen::<f64>(), // pos x
+                rng.gen::<f64>(), // pos y
             );
-            let obj = self.random_object(x);
+            let obj = self.random_object(i as usize + 1, x);
             objects.push(obj);
         }
 
         objects
     }
 }
+
+#[test]
+fn test_create_is_reproducible() {
+    let scene = |seed| Scene {
+        space_size: 1024,
+        num_objects: 10,
+        seed,
+    };
+
+    assert_eq!(scene(7).create(), scene(7).create());
+    assert_ne!(scene(7).create(), scene(8).create());
+}
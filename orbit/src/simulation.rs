@@ -0,0 +1,117 @@
+use super::object::*;
+use super::profile::*;
+use super::scene::*;
+use super::stop::*;
+
+// The parameters that describe one run of a scene, and that a batch run can
+// sweep over.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Params {
+    pub num_objects: u32,
+    pub iterations: u32,
+    pub dt: f64,
+    pub seed: u64,
+}
+
+// A scene being stepped through time under gravity and any external fields.
+pub struct Simulation<'a> {
+    pub objects: Vec<Object>,
+    pub params: Params,
+    pub step: u32,
+    pub initial_bodies: usize,
+    pub initial_energy: f64,
+    // The id for the next new body (from a collision breaking things up)
+    pub next_id: usize,
+    // The collisions in the last step, and how many merges there have been
+    pub collisions: Vec<Collision>,
+    pub merges: usize,
+    // Where the time goes, for --profile
+    pub profile: Profile,
+    // Also how far away a body has to be to have escaped
+    pub space_size: u32,
+    physics: Physics<'a>,
+    until: &'a [Until],
+    // Worked out once per step, as some conditions take a while to check
+    stopped: Option<String>,
+}
+
+impl<'a> Simulation<'a> {
+    pub fn new(params: Params, physics: Physics<'a>, until: &'a [Until], space_size: u32) -> Self {
+        let scene = Scene {
+            num_objects: params.num_objects,
+            space_size,
+            seed: params.seed,
+        };
+
+        let objects = scene.create();
+
+        let mut sim = Simulation {
+            initial_bodies: objects.len(),
+            initial_energy: total_energy(&objects),
+            next_id: next_free_id(&objects),
+            collisions: Vec::new(),
+            merges: 0,
+            objects,
+            params,
+            step: 0,
+            profile: Profile::new(),
+            space_size,
+            physics,
+            until,
+            stopped: None,
+        };
+        sim.stopped = sim.check_stop();
+        sim
+    }
+
+    pub fn time(&self) -> f64 {
+        self.step as f64 * self.params.dt
+    }
+
+    pub fn finished(&self) -> bool {
+        self.stopped.is_some()
+    }
+
+    // Why the run is over, if it is
+    pub fn stop_reason(&self) -> Option<String> {
+        self.stopped.clone()
+    }
+
+    fn check_stop(&self) -> Option<String> {
+        self.until
+            .iter()
+            .find_map(|until| until.check(self))
+            .or_else(|| {
+                if self.step >= self.params.iterations {
+                    Some(format!("ran all {} iterations", self.params.iterations))
+                } else {
+                    None
+                }
+            })
+    }
+
+    pub fn advance(&mut self) {
+        self.collisions.clear();
+        self.objects = update_all(
+            &self.objects,
+            &self.physics,
+            self.time(),
+            self.params.dt,
+            &mut self.next_id,
+            &mut self.collisions,
+            Some(&mut self.profile),
+        );
+        self.merges += self
+            .collisions
+            .iter()
+            .filter(|c| c.outcome == Outcome::Merged)
+            .count();
+        self.step += 1;
+        self.stopped = self.check_stop();
+    }
+
+    // Change in total energy relative to the start
+    pub fn energy_drift(&self) -> f64 {
+        (total_energy(&self.objects) - self.initial_energy) / self.initial_energy.abs()
+    }
+}
@@ -0,0 +1,109 @@
+use super::object::*;
+use super::simulation::*;
+
+// A reason to stop a run early. A run stops as soon as any one of its
+// conditions holds, or when it runs out of iterations.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Until {
+    // At most this many bodies are left
+    Bodies(usize),
+    // The body with this id has escaped, or any body has if there's no id
+    Escaped(Option<usize>),
+    // The energy has drifted by more than this fraction
+    Drift(f64),
+    // The simulation has reached this time
+    Time(f64),
+}
+
+/// Parse a stopping condition: `bodies:N`, `escape:BODY` (or `escape:any`),
+/// `drift:EPSILON` or `time:T`.
+pub fn parse_until(s: &str) -> Option<Until> {
+    let index = s.find(':')?;
+    let (kind, value) = (&s[..index], &s[index + 1..]);
+
+    let positive = || value.parse().ok().filter(|v: &f64| *v > 0.0);
+
+    match kind {
+        "bodies" => value.parse().ok().map(Until::Bodies),
+        "escape" if value == "any" => Some(Until::Escaped(None)),
+        "escape" => parse_body_name(value).map(|id| Until::Escaped(Some(id))),
+        "drift" => positive().map(Until::Drift),
+        "time" => positive().map(Until::Time),
+        _ => None,
+    }
+}
+
+impl Until {
+    // Why the run stopped, if this condition holds
+    pub fn check(&self, sim: &Simulation) -> Option<String> {
+        let objects = &sim.objects;
+        let radius = sim.space_size as f64;
+
+        match *self {
+            Until::Bodies(n) if objects.len() <= n => {
+                Some(format!("{} bodies remain", objects.len()))
+            }
+            Until::Escaped(id) => escaped(objects, radius)
+                .find(|o| id.is_none_or(|id| o.id == id))
+                .map(|o| format!("{} escaped", body_name(o.id))),
+            Until::Drift(epsilon) => {
+                let drift = sim.energy_drift();
+                if drift.abs() > epsilon {
+                    Some(format!("energy drift {:+.3e} exceeded {}", drift, epsilon))
+                } else {
+                    None
+                }
+            }
+            Until::Time(t) if sim.time() >= t => Some(format!("reached time {}", t)),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_parse_until() {
+    assert_eq!(Some(Until::Bodies(5)), parse_until("bodies:5"));
+    assert_eq!(Some(Until::Escaped(Some(3))), parse_until("escape:body3"));
+    assert_eq!(Some(Until::Escaped(Some(0))), parse_until("escape:sun"));
+    assert_eq!(Some(Until::Escaped(None)), parse_until("escape:any"));
+    assert_eq!(Some(Until::Drift(1e-3)), parse_until("drift:1e-3"));
+    assert_eq!(Some(Until::Time(250.0)), parse_until("time:250"));
+
+    assert_eq!(None, parse_until("bodies"));
+    assert_eq!(None, parse_until("bodies:-1"));
+    assert_eq!(None, parse_until("drift:0"));
+    assert_eq!(None, parse_until("time:never"));
+    assert_eq!(None, parse_until("heat:death"));
+}
+
+#[test]
+fn test_until_stops_simulation() {
+    let params = Params {
+        num_objects: 100,
+        iterations: 1000,
+        dt: 1.0,
+        seed: 7,
+    };
+
+    let run = |until: &[Until]| {
+        let mut sim = Simulation::new(params, Physics::default(), until, 1024);
+        while !sim.finished() {
+            sim.advance();
+        }
+        (sim.step, sim.stop_reason())
+    };
+
+    assert_eq!(
+        (1000, Some(String::from("ran all 1000 iterations"))),
+        run(&[])
+    );
+    assert_eq!(
+        (25, Some(String::from("reached time 25"))),
+        run(&[Until::Time(25.0)])
+    );
+
+    // Whichever comes first
+    let (steps, reason) = run(&[Until::Time(500.0), Until::Bodies(100)]);
+    assert!(steps < 500, "took {} steps", steps);
+    assert!(reason.unwrap().ends_with("bodies remain"));
+}
@@ -0,0 +1,170 @@
+use super::object::*;
+use super::render::*;
+use super::vec2::*;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+// The path of every body over a whole run, plus where bodies merged or broke
+// up.
+pub struct Trajectories {
+    paths: BTreeMap<usize, Vec<Vec2>>,
+    alive: BTreeSet<usize>,
+    merges: Vec<Vec2>,
+    fragmentations: Vec<Vec2>,
+}
+
+impl Trajectories {
+    pub fn new(objects: &[Object]) -> Trajectories {
+        let mut trajectories = Trajectories {
+            paths: BTreeMap::new(),
+            alive: BTreeSet::new(),
+            merges: Vec::new(),
+            fragmentations: Vec::new(),
+        };
+        trajectories.record(objects);
+        trajectories
+    }
+
+    // Add the current positions, once per frame so that the paths only grow
+    // as fast as the animation does
+    pub fn record(&mut self, objects: &[Object]) {
+        for obj in objects {
+            self.paths.entry(obj.id).or_default().push(obj.position);
+        }
+        self.alive = objects.iter().map(|o| o.id).collect();
+    }
+
+    // Mark where bodies merged or broke up, which has to be done every step
+    // as collisions are only kept for the step they happen in
+    pub fn collided(&mut self, collisions: &[Collision]) {
+        for collision in collisions {
+            match collision.outcome {
+                Outcome::Merged => self.merges.push(collision.position),
+                Outcome::Fragmented => self.fragmentations.push(collision.position),
+                Outcome::Bounced => {}
+            }
+        }
+    }
+
+    /// Draw every path as an SVG polyline in the colour of its body, with a
+    /// hollow circle where it started, a filled one where it ended (if it
+    /// survived to the end), a white cross at each merge and an orange star
+    /// wherever bodies broke up.
+    pub fn to_svg(&self, camera: &Camera) -> String {
+        let size = camera.size;
+        let mut svg = String::new();
+
+        // Writing to a String can't fail
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{0}" viewBox="0 0 {0} {0}">"#,
+            size
+        )
+        .unwrap();
+        writeln!(svg, r#"<rect width="100%" height="100%" fill="black"/>"#).unwrap();
+
+        for (id, path) in &self.paths {
+            let colour = colour_attribute(&body_colour(*id));
+            let points: Vec<String> = path
+                .iter()
+                .map(|p| {
+                    let s = camera.project(p);
+                    format!("{:.2},{:.2}", s.0, s.1)
+                })
+                .collect();
+
+            writeln!(
+                svg,
+                r#"<polyline fill="none" stroke="{}" stroke-width="1" points="{}"/>"#,
+                colour,
+                points.join(" ")
+            )
+            .unwrap();
+
+            let start = camera.project(&path[0]);
+            writeln!(
+                svg,
+                r#"<circle cx="{:.2}" cy="{:.2}" r="3" fill="none" stroke="{}"/>"#,
+                start.0, start.1, colour
+            )
+            .unwrap();
+
+            if self.alive.contains(id) {
+                let end = camera.project(&path[path.len() - 1]);
+                writeln!(
+                    svg,
+                    r#"<circle cx="{:.2}" cy="{:.2}" r="3" fill="{}"/>"#,
+                    end.0, end.1, colour
+                )
+                .unwrap();
+            }
+        }
+
+        for merge in &self.merges {
+            let m = camera.project(merge);
+            writeln!(
+                svg,
+                r#"<path d="M{:.2},{:.2} l8,8 m0,-8 l-8,8" stroke="white" stroke-width="1.5"/>"#,
+                m.0 - 4.0,
+                m.1 - 4.0
+            )
+            .unwrap();
+        }
+
+        for fragmentation in &self.fragmentations {
+            let f = camera.project(fragmentation);
+            writeln!(
+                svg,
+                r#"<path d="M{:.2},{:.2} h10 m-5,-5 v10 m-4,-1 l8,-8 m0,8 l-8,-8" stroke="orange" stroke-width="1.5"/>"#,
+                f.0 - 5.0,
+                f.1
+            )
+            .unwrap();
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+fn colour_attribute(c: &Rgba) -> String {
+    format!("rgb({},{},{})", c.0, c.1, c.2)
+}
+
+#[test]
+fn test_trajectories_record_collisions() {
+    let body = |id, x| Object {
+        id,
+        position: Vec2(x, 0.0),
+        mass: 1.0,
+        velocity: VEC_ZERO,
+        force: VEC_ZERO,
+    };
+
+    let collision = |x, outcome| Collision {
+        position: Vec2(x, 0.0),
+        outcome,
+    };
+
+    let mut trajectories = Trajectories::new(&[body(1, 0.0), body(2, 10.0)]);
+    trajectories.record(&[body(1, 1.0), body(2, 9.0)]);
+    trajectories.collided(&[
+        collision(5.0, Outcome::Merged),
+        collision(7.0, Outcome::Bounced),
+    ]);
+    trajectories.record(&[body(1, 2.0)]);
+    trajectories.collided(&[collision(3.0, Outcome::Fragmented)]);
+
+    assert_eq!(vec![Vec2(5.0, 0.0)], trajectories.merges);
+    assert_eq!(vec![Vec2(3.0, 0.0)], trajectories.fragmentations);
+    assert_eq!(3, trajectories.paths[&1].len());
+
+    let svg = trajectories.to_svg(&Camera::fit(10.0, 10));
+    assert_eq!(2, svg.matches("<polyline").count());
+    assert!(svg.contains(r#"points="0.00,0.00 1.00,0.00 2.00,0.00""#));
+    // Two starts but only one end, as body 2 didn't survive
+    assert_eq!(3, svg.matches("<circle").count());
+    assert_eq!(1, svg.matches(r#"stroke="white""#).count());
+    assert_eq!(1, svg.matches(r#"stroke="orange""#).count());
+}
@@ -0,0 +1,258 @@
+use super::object::*;
+use super::render::*;
+use super::simulation::*;
+use super::vec2::*;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::io::{stdout, Write};
+use std::time::Duration;
+
+// Each character cell is a 2x4 grid of braille dots, numbered like this:
+//   1 4
+//   2 5
+//   3 6
+//   7 8
+const DOTS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+// How often the screen is redrawn
+const TICK: Duration = Duration::from_millis(33);
+
+// A grid of braille characters, each with the colour of the last body drawn
+// in it (a cell can only have one colour).
+pub struct Canvas {
+    columns: usize,
+    rows: usize,
+    dots: Vec<u8>,
+    colours: Vec<Option<(u8, u8, u8)>>,
+}
+
+impl Canvas {
+    pub fn new(columns: usize, rows: usize) -> Canvas {
+        Canvas {
+            columns,
+            rows,
+            dots: vec![0; columns * rows],
+            colours: vec![None; columns * rows],
+        }
+    }
+
+    // Size in dots, which is what the camera looks at
+    pub fn width(&self) -> usize {
+        self.columns * 2
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows * 4
+    }
+
+    pub fn plot(&mut self, x: i64, y: i64, colour: &Rgba) {
+        if x < 0 || y < 0 || x >= self.width() as i64 || y >= self.height() as i64 {
+            return;
+        }
+
+        let (x, y) = (x as usize, y as usize);
+        let cell = (y / 4) * self.columns + x / 2;
+        self.dots[cell] |= DOTS[x % 2][y % 4];
+        self.colours[cell] = Some((colour.0, colour.1, colour.2));
+    }
+
+    // The camera only knows about square images, so centre its square in
+    // the (usually wider than tall) canvas.
+    pub fn render(&mut self, obj: &Object, camera: &Camera) {
+        let p = camera.project(&obj.position);
+        let x = p.0 + (self.width() as f64 - camera.size as f64) / 2.0;
+        let y = p.1 + (self.height() as f64 - camera.size as f64) / 2.0;
+        let radius = (obj.mass * camera.zoom / 2.0) as i64;
+        let colour = body_colour(obj.id);
+
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                if dx * dx + dy * dy <= radius * radius {
+                    self.plot(x as i64 + dx, y as i64 + dy, &colour);
+                }
+            }
+        }
+    }
+
+    pub fn row(&self, row: usize) -> impl Iterator<Item = (char, Option<(u8, u8, u8)>)> + '_ {
+        let cells = row * self.columns..(row + 1) * self.columns;
+
+        // An empty braille character shows up as a dotted box in some fonts
+        cells.map(move |i| match self.dots[i] {
+            0 => (' ', None),
+            dots => (
+                std::char::from_u32(0x2800 + dots as u32).unwrap_or(' '),
+                self.colours[i],
+            ),
+        })
+    }
+}
+
+// Puts the terminal back the way we found it, however we leave
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter() -> std::io::Result<RawTerminal> {
+        terminal::enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen, Hide)?;
+        Ok(RawTerminal)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Run the simulation live in the terminal until the user quits.
+///
+/// * space pauses and resumes, `n` steps once while paused
+/// * `+` / `-` zoom in and out, the arrow keys pan
+/// * `]` / `[` double and halve the number of steps per redraw
+/// * `q` or escape quits
+pub fn run_tui(mut sim: Simulation, space_size: f64) -> Result<Simulation, String> {
+    let io_error = |e: std::io::Error| format!("terminal error: {}", e);
+    let _terminal = RawTerminal::enter().map_err(io_error)?;
+
+    let mut paused = false;
+    let mut speed: u32 = 1;
+    let mut zoom = 1.0;
+    let mut centre = Vec2(space_size / 2.0, space_size / 2.0);
+
+    loop {
+        let (columns, rows) = terminal::size().map_err(io_error)?;
+        // Leave the bottom line for the status
+        let mut canvas = Canvas::new(columns as usize, rows.saturating_sub(1) as usize);
+        let size = canvas.width().min(canvas.height());
+        let camera = Camera {
+            centre,
+            zoom: zoom * size as f64 / space_size,
+            size,
+        };
+
+        for object in &sim.objects {
+            canvas.render(object, &camera);
+        }
+
+        let state = match sim.stop_reason() {
+            Some(reason) => format!("stopped: {}", reason),
+            None if paused => String::from("paused"),
+            None => String::from("running"),
+        };
+        let status = format!(
+            " step {}  time {:.1}  bodies {}  drift {:+.3e}  x{} {} ",
+            sim.step,
+            sim.time(),
+            sim.objects.len(),
+            sim.energy_drift(),
+            speed,
+            state
+        );
+        draw(&canvas, &status).map_err(io_error)?;
+
+        let mut steps = if paused { 0 } else { speed };
+
+        if event::poll(TICK).map_err(io_error)? {
+            if let Event::Key(KeyEvent {
+                code,
+                kind: KeyEventKind::Press,
+                ..
+            }) = event::read().map_err(io_error)?
+            {
+                // Pan by a tenth of what's visible
+                let pan = space_size / zoom / 10.0;
+                match code {
+                    KeyCode::Char('q') | KeyCode::Esc => break,
+                    KeyCode::Char(' ') => paused = !paused,
+                    KeyCode::Char('n') if paused => steps = 1,
+                    KeyCode::Char('+') | KeyCode::Char('=') => zoom *= 1.25,
+                    KeyCode::Char('-') => zoom /= 1.25,
+                    KeyCode::Char(']') => speed = speed.saturating_mul(2),
+                    KeyCode::Char('[') => speed = (speed / 2).max(1),
+                    KeyCode::Left => centre.0 -= pan,
+                    KeyCode::Right => centre.0 += pan,
+                    KeyCode::Up => centre.1 -= pan,
+                    KeyCode::Down => centre.1 += pan,
+                    _ => {}
+                }
+            }
+        }
+
+        for _ in 0..steps {
+            if sim.finished() {
+                break;
+            }
+            sim.advance();
+        }
+    }
+
+    Ok(sim)
+}
+
+fn draw(canvas: &Canvas, status: &str) -> std::io::Result<()> {
+    let mut out = stdout();
+
+    for row in 0..canvas.rows {
+        queue!(out, MoveTo(0, row as u16))?;
+
+        // Only change colour when it actually changes, it's a lot less output
+        let mut current = None;
+        for (c, colour) in canvas.row(row) {
+            if let Some((r, g, b)) = colour.filter(|_| colour != current) {
+                queue!(out, SetForegroundColor(Color::Rgb { r, g, b }))?;
+                current = colour;
+            }
+            queue!(out, Print(c))?;
+        }
+    }
+
+    queue!(
+        out,
+        MoveTo(0, canvas.rows as u16),
+        ResetColor,
+        Print(format!("{:width$}", status, width = canvas.columns))
+    )?;
+    out.flush()
+}
+
+#[test]
+fn test_canvas_plot() {
+    let mut canvas = Canvas::new(2, 1);
+    let white = Rgba(255, 255, 255, 255);
+
+    canvas.plot(0, 0, &white);
+    canvas.plot(1, 3, &white);
+    canvas.plot(3, 1, &white);
+    // Off the canvas, so ignored
+    canvas.plot(4, 0, &white);
+    canvas.plot(0, -1, &white);
+
+    let chars: Vec<char> = canvas.row(0).map(|(c, _)| c).collect();
+    assert_eq!(vec!['\u{2881}', '\u{2810}'], chars);
+    assert_eq!(Some((255, 255, 255)), canvas.row(0).next().unwrap().1);
+}
+
+#[test]
+fn test_canvas_render_centres_square_camera() {
+    // 20 dots wide but only 8 high, so the camera's square is 8 across
+    let mut canvas = Canvas::new(10, 2);
+    let camera = Camera::fit(8.0, 8);
+    let obj = Object {
+        id: 1,
+        position: Vec2(4.0, 4.0),
+        mass: 0.0,
+        velocity: VEC_ZERO,
+        force: VEC_ZERO,
+    };
+
+    canvas.render(&obj, &camera);
+
+    // The middle of the space lands on dot (10, 4): column 5 of the second row
+    assert_eq!('\u{2801}', canvas.row(1).nth(5).unwrap().0);
+}
@@ -1,4 +1,4 @@
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
 pub struct Vec2(pub f64, pub f64);
 
 pub const VEC_ZERO: Vec2 = Vec2(0.0, 0.0);
@@ -29,16 +29,20 @@ pub fn scale(a: &Vec2, d: f64) -> Vec2 {
     Vec2(a.0 * d, a.1 * d)
 }
 
+pub fn dot(a: &Vec2, b: &Vec2) -> f64 {
+    a.0 * b.0 + a.1 * b.1
+}
+
 pub fn magnitude(a: &Vec2) -> f64 {
     (a.0 * a.0 + a.1 * a.1).sqrt()
 }
 
 pub fn unit(a: &Vec2) -> Vec2 {
-    let m = magnitude(&a);
+    let m = magnitude(a);
     if m == 0.0 {
         Vec2(a.0, a.1)
     } else {
-        scale(&a, 1.0 / m)
+        scale(a, 1.0 / m)
     }
 }
 